@@ -0,0 +1,206 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::{App, InputMode, Keymap};
+
+/// The input mode the shell starts in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StartMode {
+    #[default]
+    Normal,
+    Insert,
+}
+
+/// A color of the theme: a name such as `yellow` or `light-red`, a
+/// `#rrggbb` value, or a number of the 256-color palette.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum ThemeColor {
+    Reset,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    Gray,
+    DarkGray,
+    LightRed,
+    LightGreen,
+    LightYellow,
+    LightBlue,
+    LightMagenta,
+    LightCyan,
+    White,
+    Rgb(u8, u8, u8),
+    Indexed(u8),
+}
+
+const COLORS: [(&str, ThemeColor); 17] = [
+    ("reset", ThemeColor::Reset),
+    ("black", ThemeColor::Black),
+    ("red", ThemeColor::Red),
+    ("green", ThemeColor::Green),
+    ("yellow", ThemeColor::Yellow),
+    ("blue", ThemeColor::Blue),
+    ("magenta", ThemeColor::Magenta),
+    ("cyan", ThemeColor::Cyan),
+    ("gray", ThemeColor::Gray),
+    ("dark-gray", ThemeColor::DarkGray),
+    ("light-red", ThemeColor::LightRed),
+    ("light-green", ThemeColor::LightGreen),
+    ("light-yellow", ThemeColor::LightYellow),
+    ("light-blue", ThemeColor::LightBlue),
+    ("light-magenta", ThemeColor::LightMagenta),
+    ("light-cyan", ThemeColor::LightCyan),
+    ("white", ThemeColor::White),
+];
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        let known = name.to_lowercase().replace('_', "-");
+        if let Some((_, color)) = COLORS.iter().find(|(color, _)| *color == known) {
+            return Ok(*color);
+        }
+        if let Ok(index) = known.parse::<u8>() {
+            return Ok(ThemeColor::Indexed(index));
+        }
+        let hex = known.strip_prefix('#').filter(|hex| hex.len() == 6);
+        match hex.and_then(|hex| u32::from_str_radix(hex, 16).ok()) {
+            Some(rgb) => Ok(ThemeColor::Rgb(
+                (rgb >> 16) as u8,
+                (rgb >> 8) as u8,
+                rgb as u8,
+            )),
+            None => Err(format!("unknown color {}", name)),
+        }
+    }
+}
+
+/// The colors of the UI.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// Popups, menus, the input line in insert mode, and running jobs.
+    pub accent: ThemeColor,
+    /// The selected history entry, search matches and `<h2>` titles.
+    pub highlight: ThemeColor,
+    /// Successful commands and `<h1>` titles.
+    pub success: ThemeColor,
+    /// Failed commands and error messages.
+    pub error: ThemeColor,
+    /// Messages of the shell such as expanded history commands.
+    pub info: ThemeColor,
+    /// The stderr lines of commands.
+    pub stderr: ThemeColor,
+    /// Completion descriptions and other secondary text.
+    pub muted: ThemeColor,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            accent: ThemeColor::Yellow,
+            highlight: ThemeColor::Blue,
+            success: ThemeColor::Green,
+            error: ThemeColor::Red,
+            info: ThemeColor::Magenta,
+            stderr: ThemeColor::LightRed,
+            muted: ThemeColor::DarkGray,
+        }
+    }
+}
+
+/// The settings of `config.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub theme: Theme,
+    pub keymap: Keymap,
+    pub start_mode: StartMode,
+    /// History entries kept in memory when `HISTSIZE` is not set.
+    pub history_size: usize,
+    /// Lines kept in the history file when `HISTFILESIZE` is not set.
+    pub history_file_size: usize,
+    /// The title of the input box, where `{cwd}`, `{dir}`, `{user}`,
+    /// `{host}`, `{status}` and `{jobs}` are replaced.
+    pub prompt: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            theme: Theme::default(),
+            keymap: Keymap::default(),
+            start_mode: StartMode::default(),
+            history_size: 1000,
+            history_file_size: 2000,
+            prompt: String::from("{cwd} {status} {jobs}"),
+        }
+    }
+}
+
+/// The name of this machine, for `{host}` in the prompt.
+pub fn hostname() -> String {
+    let mut name = [0u8; 256];
+    let len = unsafe {
+        if libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) != 0 {
+            return String::new();
+        }
+        name.iter().position(|c| *c == 0).unwrap_or(name.len())
+    };
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+/// Read and parse the TOML file at `path`, or `None` when it does not
+/// exist. Errors read `path:line: message`.
+pub fn read_toml<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(format!("{}: {}", path.display(), err)),
+    };
+    toml::from_str(&text).map(Some).map_err(|err| {
+        let line = err
+            .span()
+            .map_or(1, |span| text[..span.start].matches('\n').count() + 1);
+        format!("{}:{}: {}", path.display(), line, err.message())
+    })
+}
+
+impl App {
+    /// The configuration directory: `rust_shell` in `$XDG_CONFIG_HOME`
+    /// (`~/.config` by default).
+    pub fn config_dir(&self) -> Option<PathBuf> {
+        let config = match self.var("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(self.var("HOME")?).join(".config"),
+        };
+        Some(config.join("rust_shell"))
+    }
+
+    /// Read `config.toml` in the configuration directory and apply it. The
+    /// defaults stay in place when it is missing or invalid.
+    pub fn load_config(&mut self) -> Result<(), String> {
+        let path = match self.config_dir() {
+            Some(dir) => dir.join("config.toml"),
+            None => return Ok(()),
+        };
+        let config: Config = match read_toml(&path)? {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        self.keymap = config.keymap;
+        self.input_mode = match config.start_mode {
+            StartMode::Normal => InputMode::Normal,
+            StartMode::Insert => InputMode::Editing,
+        };
+        self.config = config;
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use crate::App;
+
+impl App {
+    /// The directory stack as `dirs` prints it: the current directory first,
+    /// then the directories saved by `pushd`.
+    pub fn dirs(&self) -> Vec<String> {
+        let mut dirs = vec![self.path.clone()];
+        dirs.extend(self.dir_stack.iter().cloned());
+        dirs
+    }
+
+    /// `path` with the home directory replaced by `~`.
+    pub fn tilde(&self, path: &str) -> String {
+        match self
+            .var("HOME")
+            .filter(|home| !home.is_empty() && *home != "/")
+        {
+            Some(home) if path == home => String::from("~"),
+            Some(home) if path.starts_with(&format!("{}/", home)) => {
+                format!("~{}", &path[home.len()..])
+            }
+            _ => path.to_string(),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::App;
+
+/// Killed texts kept for `yank`.
+const KILL_RING_SIZE: usize = 30;
+/// Undo steps kept for the input line.
+const UNDO_SIZE: usize = 100;
+
+/// The kind of the last edit, which decides whether the next one merges
+/// with it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum LastEdit {
+    #[default]
+    Other,
+    /// Characters typed in a row are undone together.
+    Insert,
+    /// Kills in a row make a single kill ring entry.
+    Kill,
+    /// The text yanked at this byte position, which `yank_pop` replaces.
+    Yank(usize),
+}
+
+/// The state of the input line editor besides the text in `App.input`.
+#[derive(Clone, Debug, Default)]
+pub struct Editor {
+    /// The byte position of the cursor in `App.input`.
+    pub cursor: usize,
+    /// Killed texts, most recent last.
+    kill_ring: Vec<String>,
+    /// How far `yank_pop` went back in the kill ring.
+    yank_index: usize,
+    undo: Vec<(String, usize)>,
+    redo: Vec<(String, usize)>,
+    last: LastEdit,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl App {
+    /// Save the input for `undo` before an edit of kind `edit`.
+    fn record(&mut self, edit: LastEdit) {
+        let merged = edit == LastEdit::Insert && self.editor.last == LastEdit::Insert;
+        if !merged {
+            self.editor
+                .undo
+                .push((self.input.clone(), self.editor.cursor));
+            if self.editor.undo.len() > UNDO_SIZE {
+                self.editor.undo.remove(0);
+            }
+        }
+        self.editor.redo.clear();
+        self.editor.last = edit;
+    }
+
+    /// Save the input for `undo` before an edit that is not merged with
+    /// the previous one.
+    pub(crate) fn save_undo(&mut self) {
+        self.record(LastEdit::Other);
+    }
+
+    /// The byte position of the character before the cursor.
+    fn previous_char(&self) -> usize {
+        let cursor = self.editor.cursor;
+        self.input[..cursor]
+            .chars()
+            .next_back()
+            .map_or(0, |c| cursor - c.len_utf8())
+    }
+
+    /// The byte position after the character under the cursor.
+    fn next_char(&self) -> usize {
+        let cursor = self.editor.cursor;
+        self.input[cursor..]
+            .chars()
+            .next()
+            .map_or(cursor, |c| cursor + c.len_utf8())
+    }
+
+    /// The start of the word before the cursor, where a word is made of
+    /// the characters accepted by `is_word`.
+    fn word_start(&self, is_word: fn(char) -> bool) -> usize {
+        let before = &self.input[..self.editor.cursor];
+        let end = before.trim_end_matches(|c| !is_word(c)).len();
+        before[..end].trim_end_matches(is_word).len()
+    }
+
+    /// The end of the word after the cursor.
+    fn word_end(&self, is_word: fn(char) -> bool) -> usize {
+        let after = &self.input[self.editor.cursor..];
+        let start = after.len() - after.trim_start_matches(|c| !is_word(c)).len();
+        let end = after.len() - after[start..].trim_start_matches(is_word).len();
+        self.editor.cursor + end
+    }
+
+    /// Replace the input line, with the cursor at its end. The previous line
+    /// can be restored with `undo`.
+    pub fn set_input(&mut self, input: String) {
+        if input != self.input {
+            self.record(LastEdit::Other);
+        }
+        self.editor.cursor = input.len();
+        self.input = input;
+    }
+
+    /// Take the input line for running it, leaving an empty line without
+    /// undo history.
+    pub fn take_input(&mut self) -> String {
+        self.editor.cursor = 0;
+        self.editor.undo.clear();
+        self.editor.redo.clear();
+        self.editor.last = LastEdit::Other;
+        std::mem::take(&mut self.input)
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        self.record(LastEdit::Insert);
+        self.input.insert(self.editor.cursor, c);
+        self.editor.cursor += c.len_utf8();
+    }
+
+    /// Delete the character before the cursor.
+    pub fn delete_backward(&mut self) {
+        let start = self.previous_char();
+        if start < self.editor.cursor {
+            self.record(LastEdit::Other);
+            self.input.drain(start..self.editor.cursor);
+            self.editor.cursor = start;
+        }
+    }
+
+    /// Delete the character under the cursor.
+    pub fn delete_forward(&mut self) {
+        let end = self.next_char();
+        if end > self.editor.cursor {
+            self.record(LastEdit::Other);
+            self.input.drain(self.editor.cursor..end);
+        }
+    }
+
+    fn move_to(&mut self, position: usize) {
+        self.editor.cursor = position;
+        self.editor.last = LastEdit::Other;
+    }
+
+    pub fn move_left(&mut self) {
+        self.move_to(self.previous_char());
+    }
+
+    pub fn move_right(&mut self) {
+        self.move_to(self.next_char());
+    }
+
+    pub fn move_home(&mut self) {
+        self.move_to(0);
+    }
+
+    pub fn move_end(&mut self) {
+        self.move_to(self.input.len());
+    }
+
+    /// Move to the start of the word before the cursor.
+    pub fn move_word_backward(&mut self) {
+        self.move_to(self.word_start(is_word_char));
+    }
+
+    /// Move to the end of the word after the cursor.
+    pub fn move_word_forward(&mut self) {
+        self.move_to(self.word_end(is_word_char));
+    }
+
+    /// Cut `start..end` of the input into the kill ring. A kill right after
+    /// another one adds to its entry, before it when `start` is the cursor
+    /// position it left.
+    fn kill(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        let merged = self.editor.last == LastEdit::Kill;
+        self.record(LastEdit::Kill);
+        let text: String = self.input.drain(start..end).collect();
+        let ring = &mut self.editor.kill_ring;
+        match ring.last_mut() {
+            Some(last) if merged && end == self.editor.cursor => last.insert_str(0, &text),
+            Some(last) if merged => last.push_str(&text),
+            _ => {
+                ring.push(text);
+                if ring.len() > KILL_RING_SIZE {
+                    ring.remove(0);
+                }
+            }
+        }
+        self.editor.cursor = start;
+    }
+
+    /// Kill the whitespace-separated word before the cursor.
+    pub fn kill_word_backward(&mut self) {
+        let start = self.word_start(|c| !c.is_whitespace());
+        self.kill(start, self.editor.cursor);
+    }
+
+    /// Kill from the start of the line to the cursor.
+    pub fn kill_to_start(&mut self) {
+        self.kill(0, self.editor.cursor);
+    }
+
+    /// Kill from the cursor to the end of the line.
+    pub fn kill_to_end(&mut self) {
+        self.kill(self.editor.cursor, self.input.len());
+    }
+
+    /// Insert the last killed text at the cursor.
+    pub fn yank(&mut self) {
+        let text = match self.editor.kill_ring.last() {
+            Some(text) => text.clone(),
+            None => return,
+        };
+        self.record(LastEdit::Other);
+        let start = self.editor.cursor;
+        self.input.insert_str(start, &text);
+        self.editor.cursor += text.len();
+        self.editor.yank_index = 0;
+        self.editor.last = LastEdit::Yank(start);
+    }
+
+    /// Right after a yank, replace the yanked text with the kill ring entry
+    /// before it.
+    pub fn yank_pop(&mut self) {
+        let start = match self.editor.last {
+            LastEdit::Yank(start) => start,
+            _ => return,
+        };
+        let ring = &self.editor.kill_ring;
+        let index = (self.editor.yank_index + 1) % ring.len();
+        let text = ring[ring.len() - 1 - index].clone();
+        self.input.replace_range(start..self.editor.cursor, &text);
+        self.editor.cursor = start + text.len();
+        self.editor.yank_index = index;
+    }
+
+    /// Restore the input line as it was before the last edit.
+    pub fn undo(&mut self) {
+        if let Some((input, cursor)) = self.editor.undo.pop() {
+            let current = std::mem::replace(&mut self.input, input);
+            self.editor.redo.push((current, self.editor.cursor));
+            self.editor.cursor = cursor;
+            self.editor.last = LastEdit::Other;
+        }
+    }
+
+    /// Apply again the last edit undone.
+    pub fn redo(&mut self) {
+        if let Some((input, cursor)) = self.editor.redo.pop() {
+            let current = std::mem::replace(&mut self.input, input);
+            self.editor.undo.push((current, self.editor.cursor));
+            self.editor.cursor = cursor;
+            self.editor.last = LastEdit::Other;
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::App;
+
+/// A command line entered in the shell.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub command: String,
+    /// Seconds since the Unix epoch when the command was entered.
+    pub timestamp: u64,
+    pub cwd: String,
+    /// The exit code, once the command has finished.
+    pub status: Option<i32>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+impl HistoryEntry {
+    /// The entry as a line of the history file: the timestamp, status, cwd
+    /// and command separated by tabs.
+    fn to_line(&self) -> String {
+        let status = self.status.map_or(String::new(), |code| code.to_string());
+        format!(
+            "{}\t{}\t{}\t{}\n",
+            self.timestamp,
+            status,
+            escape(&self.cwd),
+            escape(&self.command)
+        )
+    }
+
+    fn from_line(line: &str) -> Option<HistoryEntry> {
+        let mut fields = line.splitn(4, '\t');
+        let timestamp = fields.next()?.parse().ok()?;
+        let status = fields.next()?.parse().ok();
+        let cwd = unescape(fields.next()?);
+        let command = unescape(fields.next()?);
+        Some(HistoryEntry {
+            command,
+            timestamp,
+            cwd,
+            status,
+        })
+    }
+}
+
+/// Run `f` with an exclusive lock on `file`, so that shells sharing the
+/// history file never interleave their writes.
+fn locked<T>(file: &mut File, f: impl FnOnce(&mut File) -> io::Result<T>) -> io::Result<T> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let result = f(file);
+    unsafe {
+        libc::flock(file.as_raw_fd(), libc::LOCK_UN);
+    }
+    result
+}
+
+impl App {
+    fn history_size(&self, name: &str, default: usize) -> usize {
+        self.var(name)
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(default)
+    }
+
+    /// Whether `HISTCONTROL` (`ignoreboth` when unset) contains `option`.
+    fn history_control(&self, option: &str) -> bool {
+        let control = self.var("HISTCONTROL").unwrap_or("ignoreboth");
+        control.split(':').any(|value| {
+            value == option
+                || (value == "ignoreboth" && (option == "ignorespace" || option == "ignoredups"))
+        })
+    }
+
+    /// The history file: `$HISTFILE`, or `rust_shell/history` in
+    /// `$XDG_DATA_HOME` (`~/.local/share` by default). An empty `HISTFILE`
+    /// disables it.
+    pub fn history_file(&self) -> Option<PathBuf> {
+        if let Some(file) = self.var("HISTFILE") {
+            return Some(PathBuf::from(file)).filter(|_| !file.is_empty());
+        }
+        let data = match self.var("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(self.var("HOME")?).join(".local/share"),
+        };
+        Some(data.join("rust_shell").join("history"))
+    }
+
+    /// Read the history file, keeping the last `HISTSIZE` entries, and cut
+    /// the file down to its last `HISTFILESIZE` lines.
+    pub fn load_history(&mut self) -> io::Result<()> {
+        let path = match self.history_file() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut file = match OpenOptions::new().read(true).write(true).open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let file_size = self.history_size("HISTFILESIZE", self.config.history_file_size);
+
+        let lines = locked(&mut file, |file| {
+            let mut text = String::new();
+            file.read_to_string(&mut text)?;
+            let lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+            if lines.len() > file_size {
+                let kept: String = lines[lines.len() - file_size..]
+                    .iter()
+                    .map(|line| format!("{}\n", line))
+                    .collect();
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(kept.as_bytes())?;
+            }
+            Ok(lines)
+        })?;
+
+        let size = self.history_size("HISTSIZE", self.config.history_size);
+        let mut entries: Vec<HistoryEntry> = lines
+            .iter()
+            .filter_map(|line| HistoryEntry::from_line(line))
+            .collect();
+        if entries.len() > size {
+            entries.drain(..entries.len() - size);
+        }
+        entries.append(&mut self.history);
+        self.history = entries;
+        Ok(())
+    }
+
+    /// Add `command` to the history, unless it is blank or `HISTCONTROL`
+    /// ignores it. Returns whether it was added.
+    pub fn add_history(&mut self, command: &str) -> bool {
+        self.history_pending = false;
+        if command.trim().is_empty() {
+            return false;
+        }
+        if command.starts_with(' ') && self.history_control("ignorespace") {
+            return false;
+        }
+        let last = self.history.last().map(|entry| entry.command.as_str());
+        if last == Some(command) && self.history_control("ignoredups") {
+            return false;
+        }
+        if self.history_control("erasedups") {
+            self.history.retain(|entry| entry.command != command);
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        self.history.push(HistoryEntry {
+            command: command.to_string(),
+            timestamp,
+            cwd: self.path.clone(),
+            status: None,
+        });
+        let size = self.history_size("HISTSIZE", self.config.history_size);
+        if self.history.len() > size {
+            self.history.drain(..self.history.len() - size);
+        }
+        self.history_pending = true;
+        true
+    }
+
+    /// Record the exit code of the command added last and append it to the
+    /// history file. With `erasedups`, the older copies of the command are
+    /// removed from the file too.
+    pub fn save_history(&mut self, status: i32) -> io::Result<()> {
+        if !std::mem::take(&mut self.history_pending) {
+            return Ok(());
+        }
+        let entry = match self.history.last_mut() {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        entry.status = Some(status);
+        let line = entry.to_line();
+        let command = entry.command.clone();
+        let erase = self.history_control("erasedups");
+
+        let path = match self.history_file() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+        locked(&mut file, |file| {
+            if !erase {
+                return file.write_all(line.as_bytes());
+            }
+            let mut text = String::new();
+            file.read_to_string(&mut text)?;
+            let mut kept: String = text
+                .lines()
+                .filter(|old| {
+                    HistoryEntry::from_line(old).is_none_or(|old| old.command != command)
+                })
+                .map(|old| format!("{}\n", old))
+                .collect();
+            kept.push_str(&line);
+            // Appending writes go to the end, which is the start once cut.
+            file.set_len(0)?;
+            file.write_all(kept.as_bytes())
+        })
+    }
+}
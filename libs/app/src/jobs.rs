@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::App;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done { code: i32, signal: Option<i32> },
+}
+
+/// A pipeline started by the shell, in the foreground or in the background.
+pub struct Job {
+    /// The number used in job specs such as `%1`.
+    pub number: usize,
+    /// The id carried by the pipeline's `ExecEvent`s.
+    pub id: u32,
+    pub pgid: u32,
+    pub pids: Vec<u32>,
+    pub command: String,
+    pub state: JobState,
+    /// Everything the job printed, in the Outputs panel markup.
+    pub output: String,
+    /// Set to stop the subshells running the job's functions and
+    /// builtins, which have no process to signal.
+    pub stop: Option<Arc<AtomicBool>>,
+}
+
+/// The streams of a subshell: a copy of the shell running a function or a
+/// builtin on a thread of its own, as a pipeline stage or in the background.
+pub struct Subshell {
+    /// Where what it prints in the Outputs panel goes, without the markup.
+    pub stdout: Option<File>,
+    pub stderr: Option<File>,
+    /// Set by the shell to stop it, as a signal would stop a process.
+    pub stop: Arc<AtomicBool>,
+    /// The status it ends with once it was stopped or its stdout closed.
+    pub stopped: Option<i32>,
+}
+
+impl Job {
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            JobState::Running => "Running",
+            JobState::Stopped => "Stopped",
+            JobState::Done { .. } => "Done",
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, JobState::Done { .. })
+    }
+}
+
+impl App {
+    /// Add a running job under the lowest free number and return that number.
+    pub fn add_job(&mut self, id: u32, pgid: u32, pids: Vec<u32>, command: String) -> usize {
+        let mut number = 1;
+        while self.jobs.iter().any(|job| job.number == number) {
+            number += 1;
+        }
+        self.jobs.push(Job {
+            number,
+            id,
+            pgid,
+            pids,
+            command,
+            state: JobState::Running,
+            output: String::new(),
+            stop: None,
+        });
+        number
+    }
+
+    pub fn job(&self, number: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.number == number)
+    }
+
+    pub fn job_mut(&mut self, number: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.number == number)
+    }
+
+    pub fn remove_job(&mut self, number: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.number == number)?;
+        if self.output_job == Some(number) {
+            self.output_job = None;
+        }
+        Some(self.jobs.remove(index))
+    }
+
+    /// The job `fg` and `bg` act on by default: the most recent one not done.
+    pub fn current_job(&self) -> Option<usize> {
+        self.jobs
+            .iter()
+            .rev()
+            .find(|job| !job.is_done())
+            .map(|job| job.number)
+    }
+
+    /// Resolve a job spec: `%n`, `%%`, `%+` or `%prefix` of the command line.
+    pub fn find_job(&self, spec: &str) -> Option<usize> {
+        let spec = spec.strip_prefix('%').unwrap_or(spec);
+        match spec {
+            "" | "%" | "+" => self.current_job(),
+            _ => match spec.parse::<usize>() {
+                Ok(number) => self.job(number).map(|job| job.number),
+                Err(_) => self
+                    .jobs
+                    .iter()
+                    .rev()
+                    .find(|job| job.command.starts_with(spec))
+                    .map(|job| job.number),
+            },
+        }
+    }
+}
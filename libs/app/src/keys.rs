@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+
+use crate::{read_toml, App, InputMode, Keymap};
+
+/// Something a key does, which the key bindings file can bind to any key
+/// of a mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    EnterInsert,
+    Quit,
+    ToggleStderr,
+    NextOutputJob,
+    ShowDirs,
+    ViCancel,
+    ExitInsert,
+    Run,
+    Complete,
+    BrowseHistory,
+    SearchHistory,
+    MoveLeft,
+    MoveRight,
+    MoveHome,
+    MoveEnd,
+    MoveWordBackward,
+    MoveWordForward,
+    DeleteBackward,
+    DeleteForward,
+    KillWordBackward,
+    KillToStart,
+    KillToEnd,
+    Yank,
+    YankPop,
+    Undo,
+    Redo,
+    OpenEditor,
+    AcceptCompletion,
+    CancelCompletion,
+    NextCompletion,
+    PreviousCompletion,
+    CompletionUp,
+    CompletionDown,
+    AcceptHistory,
+    CancelHistory,
+    HistoryNext,
+    AcceptMatch,
+    CancelSearch,
+    NextMatch,
+    PreviousMatch,
+    NextPage,
+    PreviousPage,
+    JumpToDir,
+    CloseDirs,
+    NextDir,
+    PreviousDir,
+    CloseHelper,
+    Detach,
+    Interrupt,
+    StopJob,
+    QuitJob,
+}
+
+/// The name of each action in the key bindings file, and what it does.
+#[rustfmt::skip]
+const ACTIONS: [(Action, &str, &str); 51] = [
+    (Action::EnterInsert, "enter-insert", "enter insert mode"),
+    (Action::Quit, "quit", "quit the shell"),
+    (Action::ToggleStderr, "toggle-stderr", "show/hide stderr output"),
+    (Action::NextOutputJob, "next-output-job", "switch the output to the next job's buffer"),
+    (Action::ShowDirs, "show-dirs", "show the directory stack"),
+    (Action::ViCancel, "vi-cancel", "cancel the vi command being typed"),
+    (Action::ExitInsert, "exit-insert", "exit insert mode"),
+    (Action::Run, "run", "launch the command"),
+    (Action::Complete, "complete", "enter completion mode"),
+    (Action::BrowseHistory, "browse-history", "enter history mode"),
+    (Action::SearchHistory, "search-history", "search the history"),
+    (Action::MoveLeft, "move-left", "move back one character"),
+    (Action::MoveRight, "move-right", "move forward one character"),
+    (Action::MoveHome, "move-home", "move to the start of the line"),
+    (Action::MoveEnd, "move-end", "move to the end of the line"),
+    (Action::MoveWordBackward, "move-word-backward", "move to the previous word"),
+    (Action::MoveWordForward, "move-word-forward", "move to the next word"),
+    (Action::DeleteBackward, "delete-backward", "delete the character before the cursor"),
+    (Action::DeleteForward, "delete-forward", "delete the character under the cursor"),
+    (Action::KillWordBackward, "kill-word-backward", "cut the word before the cursor"),
+    (Action::KillToStart, "kill-to-start", "cut to the start of the line"),
+    (Action::KillToEnd, "kill-to-end", "cut to the end of the line"),
+    (Action::Yank, "yank", "paste the last cut text"),
+    (Action::YankPop, "yank-pop", "replace the text just pasted with an older cut"),
+    (Action::Undo, "undo", "undo the last edit"),
+    (Action::Redo, "redo", "redo the last edit undone"),
+    (Action::OpenEditor, "open-editor", "edit the line in $EDITOR"),
+    (Action::AcceptCompletion, "accept-completion", "use the selected completion"),
+    (Action::CancelCompletion, "cancel-completion", "exit completion mode"),
+    (Action::NextCompletion, "next-completion", "select the next completion"),
+    (Action::PreviousCompletion, "previous-completion", "select the previous completion"),
+    (Action::CompletionUp, "completion-up", "move up in the completion menu"),
+    (Action::CompletionDown, "completion-down", "move down in the completion menu"),
+    (Action::AcceptHistory, "accept-history", "use the selected history command"),
+    (Action::CancelHistory, "cancel-history", "exit history mode"),
+    (Action::HistoryNext, "history-next", "select the next history command"),
+    (Action::AcceptMatch, "accept-match", "use the selected command"),
+    (Action::CancelSearch, "cancel-search", "exit search mode"),
+    (Action::NextMatch, "next-match", "select the next match"),
+    (Action::PreviousMatch, "previous-match", "select the previous match"),
+    (Action::NextPage, "next-page", "move down 10 matches"),
+    (Action::PreviousPage, "previous-page", "move up 10 matches"),
+    (Action::JumpToDir, "jump-to-dir", "jump to the selected directory"),
+    (Action::CloseDirs, "close-dirs", "close the directory stack"),
+    (Action::NextDir, "next-dir", "select the next directory"),
+    (Action::PreviousDir, "previous-dir", "select the previous directory"),
+    (Action::CloseHelper, "close-helper", "exit helper mode"),
+    (Action::Detach, "detach", "detach the running command as a background job"),
+    (Action::Interrupt, "interrupt", "interrupt the running command (SIGINT)"),
+    (Action::StopJob, "stop-job", "stop the running command (SIGTSTP)"),
+    (Action::QuitJob, "quit-job", "quit the running command (SIGQUIT)"),
+];
+
+impl Action {
+    fn entry(self) -> &'static (Action, &'static str, &'static str) {
+        ACTIONS
+            .iter()
+            .find(|(action, _, _)| *action == self)
+            .unwrap()
+    }
+
+    /// The name of the action in the key bindings file, such as `enter-insert`.
+    pub fn name(self) -> &'static str {
+        self.entry().1
+    }
+
+    pub fn description(self) -> &'static str {
+        self.entry().2
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS
+            .iter()
+            .find(|(_, known, _)| *known == name)
+            .map(|(action, _, _)| *action)
+    }
+
+    /// Whether the action edits the input line or moves its cursor.
+    pub fn edits_line(self) -> bool {
+        matches!(
+            self,
+            Action::MoveLeft
+                | Action::MoveRight
+                | Action::MoveHome
+                | Action::MoveEnd
+                | Action::MoveWordBackward
+                | Action::MoveWordForward
+                | Action::DeleteBackward
+                | Action::DeleteForward
+                | Action::KillWordBackward
+                | Action::KillToStart
+                | Action::KillToEnd
+                | Action::Yank
+                | Action::YankPop
+                | Action::Undo
+                | Action::Redo
+        )
+    }
+}
+
+/// The sections of the key bindings file, one per input mode, with the
+/// title of their part of the Helper popup. `vi` is Normal mode with the
+/// vi keymap.
+pub const MODES: [(&str, &str); 9] = [
+    ("normal", "NORMAL MODE"),
+    ("vi", "VI COMMAND MODE"),
+    ("editing", "INSERT MODE"),
+    ("completion", "COMPLETION MODE"),
+    ("history", "HISTORY MODE"),
+    ("search", "HISTORY SEARCH"),
+    ("dirs", "DIRECTORY STACK"),
+    ("helper", "HELPER"),
+    ("output", "OUTPUT MODE"),
+];
+
+const DEFAULT_BINDINGS: [(&str, &str, Action); 75] = [
+    ("normal", "i", Action::EnterInsert),
+    ("normal", "q", Action::Quit),
+    ("normal", "s", Action::ToggleStderr),
+    ("normal", "j", Action::NextOutputJob),
+    ("normal", "d", Action::ShowDirs),
+    ("vi", "Enter", Action::Run),
+    ("vi", "q", Action::Quit),
+    ("vi", "s", Action::ToggleStderr),
+    ("vi", "j", Action::NextOutputJob),
+    ("vi", "Ctrl-D", Action::ShowDirs),
+    ("vi", "Ctrl-R", Action::Redo),
+    ("vi", "Left", Action::MoveLeft),
+    ("vi", "Right", Action::MoveRight),
+    ("vi", "Home", Action::MoveHome),
+    ("vi", "End", Action::MoveEnd),
+    ("vi", "Esc", Action::ViCancel),
+    ("editing", "Esc", Action::ExitInsert),
+    ("editing", "Enter", Action::Run),
+    ("editing", "Tab", Action::Complete),
+    ("editing", "Down", Action::BrowseHistory),
+    ("editing", "Ctrl-R", Action::SearchHistory),
+    ("editing", "Ctrl-B", Action::MoveLeft),
+    ("editing", "Left", Action::MoveLeft),
+    ("editing", "Ctrl-F", Action::MoveRight),
+    ("editing", "Right", Action::MoveRight),
+    ("editing", "Ctrl-A", Action::MoveHome),
+    ("editing", "Home", Action::MoveHome),
+    ("editing", "Ctrl-E", Action::MoveEnd),
+    ("editing", "End", Action::MoveEnd),
+    ("editing", "Alt-B", Action::MoveWordBackward),
+    ("editing", "Alt-F", Action::MoveWordForward),
+    ("editing", "Backspace", Action::DeleteBackward),
+    ("editing", "Ctrl-H", Action::DeleteBackward),
+    ("editing", "Delete", Action::DeleteForward),
+    ("editing", "Ctrl-D", Action::DeleteForward),
+    ("editing", "Ctrl-W", Action::KillWordBackward),
+    ("editing", "Ctrl-U", Action::KillToStart),
+    ("editing", "Ctrl-K", Action::KillToEnd),
+    ("editing", "Ctrl-Y", Action::Yank),
+    ("editing", "Alt-Y", Action::YankPop),
+    ("editing", "Ctrl-Z", Action::Undo),
+    ("editing", "Ctrl-_", Action::Undo),
+    ("editing", "Alt-/", Action::Redo),
+    ("completion", "Enter", Action::AcceptCompletion),
+    ("completion", "Esc", Action::CancelCompletion),
+    ("completion", "Tab", Action::NextCompletion),
+    ("completion", "Right", Action::NextCompletion),
+    ("completion", "Shift-Tab", Action::PreviousCompletion),
+    ("completion", "Left", Action::PreviousCompletion),
+    ("completion", "Up", Action::CompletionUp),
+    ("completion", "Down", Action::CompletionDown),
+    ("completion", "Backspace", Action::DeleteBackward),
+    ("history", "Enter", Action::AcceptHistory),
+    ("history", "Esc", Action::CancelHistory),
+    ("history", "Tab", Action::HistoryNext),
+    ("search", "Enter", Action::AcceptMatch),
+    ("search", "Esc", Action::CancelSearch),
+    ("search", "Ctrl-G", Action::CancelSearch),
+    ("search", "Ctrl-R", Action::NextMatch),
+    ("search", "Down", Action::NextMatch),
+    ("search", "Up", Action::PreviousMatch),
+    ("search", "PageDown", Action::NextPage),
+    ("search", "PageUp", Action::PreviousPage),
+    ("search", "Backspace", Action::DeleteBackward),
+    ("dirs", "Enter", Action::JumpToDir),
+    ("dirs", "Esc", Action::CloseDirs),
+    ("dirs", "Tab", Action::NextDir),
+    ("dirs", "Down", Action::NextDir),
+    ("dirs", "Shift-Tab", Action::PreviousDir),
+    ("dirs", "Up", Action::PreviousDir),
+    ("helper", "Esc", Action::CloseHelper),
+    ("output", "Esc", Action::Detach),
+    ("output", "Ctrl-C", Action::Interrupt),
+    ("output", "Ctrl-Z", Action::StopJob),
+    ("output", "Ctrl-\\", Action::QuitJob),
+];
+
+/// The vi commands, which are not actions and cannot be rebound, for the
+/// Helper popup.
+const VI_COMMANDS: [(&str, &str); 5] = [
+    ("h l w b e 0 $ f t", "motions, with a count"),
+    (
+        "d c y",
+        "delete, change, copy a motion or a text object (iw, a\", i( ...)",
+    ),
+    ("x p u", "delete a character, paste, undo"),
+    (
+        "i a A I",
+        "insert before/after the cursor, at the end/start",
+    ),
+    ("v", "edit the line in $EDITOR"),
+];
+
+/// The key bindings of each mode, in the order the Helper popup lists them.
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    modes: HashMap<&'static str, Vec<(String, Action)>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut modes: HashMap<&'static str, Vec<(String, Action)>> = HashMap::new();
+        for (mode, key, action) in DEFAULT_BINDINGS {
+            modes
+                .entry(mode)
+                .or_default()
+                .push((key.to_string(), action));
+        }
+        Bindings { modes }
+    }
+}
+
+impl Bindings {
+    /// The action bound to `key` in `mode`.
+    pub fn action(&self, mode: &str, key: &str) -> Option<Action> {
+        self.modes
+            .get(mode)?
+            .iter()
+            .find(|(bound, _)| bound == key)
+            .map(|(_, action)| *action)
+    }
+
+    /// The keys bound to `action` in `mode`.
+    pub fn keys(&self, mode: &str, action: Action) -> Vec<&str> {
+        self.modes.get(mode).map_or(Vec::new(), |keys| {
+            keys.iter()
+                .filter(|(_, bound)| *bound == action)
+                .map(|(key, _)| key.as_str())
+                .collect()
+        })
+    }
+
+    /// Bind `key` to `action` in `mode`, or unbind it for `None`.
+    fn bind(&mut self, mode: &'static str, key: String, action: Option<Action>) {
+        let keys = self.modes.entry(mode).or_default();
+        match (keys.iter_mut().find(|(bound, _)| *bound == key), action) {
+            (Some(binding), Some(action)) => binding.1 = action,
+            (None, Some(action)) => keys.push((key, action)),
+            (_, None) => keys.retain(|(bound, _)| *bound != key),
+        }
+    }
+}
+
+/// The canonical name of a key as written in the key bindings file, such as
+/// `Ctrl-R` for `ctrl-r` or `Esc` for `escape`.
+pub fn normalize_key(key: &str) -> Option<String> {
+    let mut rest = key;
+    let (mut ctrl, mut alt, mut shift) = (false, false, false);
+    loop {
+        let prefix = ["ctrl-", "alt-", "shift-"].into_iter().find(|prefix| {
+            rest.len() > prefix.len()
+                && rest
+                    .get(..prefix.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+        });
+        match prefix {
+            Some("ctrl-") => ctrl = true,
+            Some("alt-") => alt = true,
+            Some(_) => shift = true,
+            None => break,
+        }
+        rest = &rest[prefix.unwrap().len()..];
+    }
+    let mut chars = rest.chars();
+    let base = match (chars.next(), chars.next()) {
+        (Some(c), None) if shift => c.to_uppercase().to_string(),
+        (Some(c), None) if ctrl || alt => c.to_uppercase().to_string(),
+        (Some(c), None) => c.to_string(),
+        _ => {
+            let named = match rest.to_lowercase().as_str() {
+                "enter" | "return" => "Enter",
+                "esc" | "escape" => "Esc",
+                "tab" if shift => return Some("Shift-Tab".to_string()),
+                "tab" => "Tab",
+                "backtab" => return Some("Shift-Tab".to_string()),
+                "backspace" => "Backspace",
+                "delete" | "del" => "Delete",
+                "insert" => "Insert",
+                "space" => "Space",
+                "up" => "Up",
+                "down" => "Down",
+                "left" => "Left",
+                "right" => "Right",
+                "home" => "Home",
+                "end" => "End",
+                "pageup" => "PageUp",
+                "pagedown" => "PageDown",
+                name => match name.strip_prefix('f').map(str::parse::<u8>) {
+                    Some(Ok(n)) => return Some(format!("{}F{}", modifiers(ctrl, alt), n)),
+                    _ => return None,
+                },
+            };
+            named.to_string()
+        }
+    };
+    Some(format!("{}{}", modifiers(ctrl, alt), base))
+}
+
+fn modifiers(ctrl: bool, alt: bool) -> &'static str {
+    match (ctrl, alt) {
+        (true, true) => "Ctrl-Alt-",
+        (true, false) => "Ctrl-",
+        (false, true) => "Alt-",
+        (false, false) => "",
+    }
+}
+
+impl App {
+    /// The section of the key bindings for the current input mode.
+    pub fn binding_mode(&self) -> &'static str {
+        match self.input_mode {
+            InputMode::Normal if self.keymap == Keymap::Vi => "vi",
+            InputMode::Normal => "normal",
+            InputMode::Editing => "editing",
+            InputMode::Completion => "completion",
+            InputMode::History => "history",
+            InputMode::Search => "search",
+            InputMode::Dirs => "dirs",
+            InputMode::Helper => "helper",
+            InputMode::Output => "output",
+        }
+    }
+
+    /// Apply the key bindings file, `keys.toml` in the configuration
+    /// directory. Each `[mode]` table maps keys to action names, or to
+    /// `none` to unbind them. Returns one message per problem found, the
+    /// other bindings being applied anyway.
+    pub fn load_keys(&mut self) -> Result<(), Vec<String>> {
+        let path = match self.config_dir() {
+            Some(dir) => dir.join("keys.toml"),
+            None => return Ok(()),
+        };
+        let tables: HashMap<String, HashMap<String, String>> = match read_toml(&path) {
+            Ok(Some(tables)) => tables,
+            Ok(None) => return Ok(()),
+            Err(err) => return Err(vec![err]),
+        };
+        let mut errors = Vec::new();
+        let mut names: Vec<&String> = tables.keys().collect();
+        names.sort();
+        for name in names {
+            let mode = match MODES.iter().find(|(mode, _)| mode == name) {
+                Some((mode, _)) => *mode,
+                None => {
+                    errors.push(format!("{}: unknown mode [{}]", path.display(), name));
+                    continue;
+                }
+            };
+            let mut keys: Vec<(&String, &String)> = tables[name].iter().collect();
+            keys.sort();
+            for (key, action) in keys {
+                let key = match normalize_key(key) {
+                    Some(key) => key,
+                    None => {
+                        errors.push(format!(
+                            "{}: [{}] unknown key {}",
+                            path.display(),
+                            mode,
+                            key
+                        ));
+                        continue;
+                    }
+                };
+                match action.as_str() {
+                    "none" => self.bindings.bind(mode, key, None),
+                    name => match Action::from_name(name) {
+                        Some(action) => self.bindings.bind(mode, key, Some(action)),
+                        None => errors.push(format!(
+                            "{}: [{}] unknown action {}",
+                            path.display(),
+                            mode,
+                            name
+                        )),
+                    },
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The Helper popup: the keys bound in each mode of the active keymap,
+    /// then the builtin commands.
+    pub fn help(&self) -> Vec<String> {
+        let mut lines = vec![String::from("<h1>Keybindings:</h1>")];
+        for (mode, title) in MODES {
+            let skipped = match self.keymap {
+                Keymap::Emacs => "vi",
+                Keymap::Vi => "normal",
+            };
+            if mode == skipped {
+                continue;
+            }
+            lines.push(format!("    <h2>{}</h2>", title));
+            let mut seen: Vec<Action> = Vec::new();
+            for (_, action) in self.bindings.modes.get(mode).into_iter().flatten() {
+                if seen.contains(action) {
+                    continue;
+                }
+                seen.push(*action);
+                let keys = self.bindings.keys(mode, *action).join("/");
+                lines.push(help_line(&keys, action.description()));
+            }
+            if mode == "vi" {
+                for (keys, description) in VI_COMMANDS {
+                    lines.push(help_line(keys, description));
+                }
+            }
+        }
+        lines.extend(self.helper.iter().cloned());
+        lines
+    }
+}
+
+fn help_line(keys: &str, description: &str) -> String {
+    format!(
+        "        <c>{}:</c>{:width$}<i>{}</i>",
+        keys,
+        "",
+        description,
+        width = 7usize.saturating_sub(keys.len()).max(1)
+    )
+}
@@ -1,3 +1,29 @@
+mod config;
+mod dirs;
+mod editor;
+mod history;
+mod jobs;
+mod keys;
+mod path_cache;
+mod search;
+mod vars;
+mod vi;
+
+pub use config::*;
+pub use editor::*;
+pub use history::*;
+pub use jobs::*;
+pub use keys::*;
+pub use path_cache::*;
+pub use search::*;
+pub use vars::*;
+pub use vi::*;
+
+use std::collections::BTreeMap;
+use std::os::fd::OwnedFd;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
 pub enum InputMode {
     Normal,
     Editing,
@@ -5,26 +31,160 @@ pub enum InputMode {
     History,
     Helper,
     Output,
+    Dirs,
+    Search,
+}
+
+/// How the last command finished.
+#[derive(Clone, Copy)]
+pub struct CommandStatus {
+    pub code: i32,
+    pub signal: Option<i32>,
+    pub duration: Duration,
+}
+
+const SIGNALS: [(i32, &str); 14] = [
+    (1, "SIGHUP"),
+    (2, "SIGINT"),
+    (3, "SIGQUIT"),
+    (4, "SIGILL"),
+    (6, "SIGABRT"),
+    (8, "SIGFPE"),
+    (9, "SIGKILL"),
+    (11, "SIGSEGV"),
+    (13, "SIGPIPE"),
+    (14, "SIGALRM"),
+    (15, "SIGTERM"),
+    (18, "SIGCONT"),
+    (19, "SIGSTOP"),
+    (20, "SIGTSTP"),
+];
+
+/// The conventional name of a signal number, such as `SIGINT` for 2.
+pub fn signal_name(signal: i32) -> String {
+    match SIGNALS.iter().find(|(number, _)| *number == signal) {
+        Some((_, name)) => name.to_string(),
+        None => format!("signal {}", signal),
+    }
+}
+
+/// The number of a signal given as `9`, `KILL` or `SIGKILL`.
+pub fn signal_number(name: &str) -> Option<i32> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Some(number);
+    }
+    let name = name.to_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS
+        .iter()
+        .find(|(_, known)| known[3..] == *name)
+        .map(|(number, _)| *number)
+}
+
+/// Escape text shown in the Outputs panel so that it is never read as markup:
+/// `&` becomes `&amp;` and `<` becomes `&lt;`.
+pub fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;")
+}
+
+/// The text escaped by `escape_markup`.
+pub fn unescape_markup(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&amp;", "&")
+}
+
+/// A message sent to the main loop by the task watching a pipeline.
+pub enum ExecEvent {
+    Output { id: u32, line: String, stderr: bool },
+    Exit { id: u32, code: i32, signal: Option<i32> },
 }
 
 pub struct App {
+    pub config: Config,
     pub input: String,
+    pub editor: Editor,
+    pub keymap: Keymap,
+    pub bindings: Bindings,
+    pub vi: ViState,
+    /// Set by `v` in vi command mode for the shell to open `$EDITOR`.
+    pub open_editor: bool,
     pub command: String,
     pub input_mode: InputMode,
     pub output: String,
     pub path: String,
+    /// The directories saved by `pushd`, most recent first.
+    pub dir_stack: Vec<String>,
+    pub dirs_index: usize,
     pub completion: Vec<String>,
     pub completion_display: Vec<String>,
+    /// A short description of each completion, empty when it has none.
+    pub completion_descriptions: Vec<String>,
     pub completion_index: usize,
-    pub history: Vec<String>,
+    /// The text before the cursor the candidates were found for, and all
+    /// of them as line, display and description. Typing more of the word
+    /// only filters them.
+    pub completion_base: Option<String>,
+    pub completion_cache: Vec<(String, String, String)>,
+    /// The output of the commands and the files read by the completers,
+    /// by command and directory or by path, with when they were read.
+    pub completion_sources: BTreeMap<String, (Instant, Option<String>)>,
+    pub history: Vec<HistoryEntry>,
     pub history_index: usize,
+    /// Whether the last history entry still waits for its exit code.
+    pub history_pending: bool,
+    pub search_query: String,
+    pub search_results: Vec<SearchMatch>,
+    pub search_index: usize,
+    /// The builtin commands part of the Helper popup, after the key bindings.
     pub helper: Vec<String>,
+    pub status: Option<CommandStatus>,
+    pub pipefail: bool,
+    pub show_stderr: bool,
+    pub jobs: Vec<Job>,
+    /// The job the Outputs panel is attached to while it runs.
+    pub foreground: Option<usize>,
+    /// Set by the interrupt key while a command line runs, so that none of
+    /// its remaining commands start.
+    pub interrupted: bool,
+    /// Set by the quit key. A running command line stops and the shell
+    /// exits.
+    pub quit: bool,
+    /// The job whose buffer the Outputs panel shows instead of `output`.
+    pub output_job: Option<usize>,
+    pub next_id: u32,
+    pub vars: BTreeMap<String, Variable>,
+    /// The aliases defined with `alias`, by name.
+    pub aliases: BTreeMap<String, String>,
+    /// The functions defined in the shell, by name, with the source of
+    /// their body.
+    pub functions: BTreeMap<String, String>,
+    /// The positional parameters `$1`, `$2`... of the running function.
+    pub positional: Vec<String>,
+    /// The function calls in progress, innermost last.
+    pub frames: Vec<Frame>,
+    /// How many loops the running command is in, for `break` and
+    /// `continue`.
+    pub loops: usize,
+    /// What the commands run inside the shell read: the stdin of a
+    /// function or builtin redirected or run as a pipeline stage.
+    pub stdin: Option<OwnedFd>,
+    /// Set when this is a subshell.
+    pub subshell: Option<Subshell>,
+    pub path_cache: PathCache,
+    pub events_tx: UnboundedSender<ExecEvent>,
+    pub events_rx: UnboundedReceiver<ExecEvent>,
 }
 
 impl Default for App {
     fn default() -> Self {
+        let (events_tx, events_rx) = unbounded_channel();
         App {
+            config: Config::default(),
             input: String::new(),
+            editor: Editor::default(),
+            keymap: Keymap::default(),
+            bindings: Bindings::default(),
+            vi: ViState::default(),
+            open_editor: false,
             command: String::new(),
             input_mode: InputMode::Normal,
             output: String::new(),
@@ -33,31 +193,76 @@ impl Default for App {
                 .to_str()
                 .unwrap()
                 .to_string(),
+            dir_stack: Vec::new(),
+            dirs_index: 0,
             completion: Vec::new(),
             completion_display: Vec::new(),
+            completion_descriptions: Vec::new(),
             completion_index: 0,
+            completion_base: None,
+            completion_cache: Vec::new(),
+            completion_sources: BTreeMap::new(),
             history: Vec::new(),
             history_index: 0,
+            history_pending: false,
+            search_query: String::new(),
+            search_results: Vec::new(),
+            search_index: 0,
             helper: vec![
-                String::from("<h1>Keybindings:</h1>"),
-                String::from("    <h2>NORMAL MODE</h2>"),
-                String::from("        <c>I:</c>      <i>enter insert mode</i>"),
-                String::from("        <c>O:</c>      <i>quit shell</i>"),
-                String::from("    <h2>INSERT MODE</h2>"),
-                String::from("        <c>Tab:</c>    <i>enable completion mode</i>"),
-                String::from("        <c>Down:</c>   <i>enable history mode</i>"),
-                String::from("    <h2>COMPLETION MODE</h2>"),
-                String::from("        <c>Tab:</c>    <i>select completion pattern</i>"),
-                String::from("        <c>Enter:</c>  <i>use selected completion pattern</i>"),
-                String::from("        <c>Esc:</c>    <i>exit completion mode</i>"),
-                String::from("    <h2>HISTORY MODE</h2>"),
-                String::from("        <c>Tab:</c>    <i>select history command</i>"),
-                String::from("        <c>Enter:</c>  <i>use selected history command</i>"),
-                String::from("        <c>Esc:</c>    <i>exit history mode</i>"),
                 String::from("<h1>Custom commands:</h1>"),
                 String::from("    <c>help:</c>    <i>show helping popup</i>"),
                 String::from("    <c>c:</c>       <i>clear output</i>"),
-            ]
+                String::from("    <c>cd:</c>      <i>cd [dir|-] changes directory ($HOME by default)</i>"),
+                String::from("    <c>pushd:</c>   <i>pushd dir|+n saves the directory and changes to dir</i>"),
+                String::from("    <c>popd:</c>    <i>popd [+n] goes back to the last saved directory</i>"),
+                String::from("    <c>history:</c> <i>history [n] lists commands for !n, !! and !prefix</i>"),
+                String::from("    <c>dirs:</c>    <i>dirs [-v] shows the directory stack</i>"),
+                String::from("    <c>set:</c>     <i>list variables, set -o/+o pipefail to toggle pipefail</i>"),
+                String::from("    <c>set -o vi:</c> <i>edit the line with vi keys in Normal mode (set -o emacs to go back)</i>"),
+                String::from("    <c>export:</c>  <i>export NAME[=value] passes a variable to commands</i>"),
+                String::from("    <c>unset:</c>   <i>unset [-f] NAME removes a variable (or function)</i>"),
+                String::from("    <c>alias:</c>   <i>alias [name[=value]] defines or lists aliases</i>"),
+                String::from("    <c>unalias:</c> <i>unalias name|-a removes aliases</i>"),
+                String::from("    <c>local:</c>   <i>local NAME[=value] sets a variable until the function returns</i>"),
+                String::from("    <c>return:</c>  <i>return [n] leaves the function with status n</i>"),
+                String::from("    <c>shift:</c>   <i>shift [n] drops the first n positional parameters</i>"),
+                String::from("    <c>break:</c>   <i>break [n] leaves the loop (continue [n] goes on with the next iteration)</i>"),
+                String::from("    <c>exit:</c>    <i>exit [n] exits the shell with status n</i>"),
+                String::from("    <c>env:</c>     <i>list exported variables</i>"),
+                String::from("    <c>cmd &:</c>   <i>run cmd as a background job</i>"),
+                String::from("    <c>jobs:</c>    <i>list jobs (-l to show process groups)</i>"),
+                String::from("    <c>fg:</c>      <i>fg %n brings job n back to the foreground</i>"),
+                String::from("    <c>bg:</c>      <i>bg %n continues stopped job n in the background</i>"),
+                String::from("    <c>kill:</c>    <i>kill [-sig] %n|pid sends a signal (SIGTERM)</i>"),
+                String::from("    <c>wait:</c>    <i>wait [%n] for background jobs to finish</i>"),
+            ],
+            status: None,
+            pipefail: false,
+            show_stderr: true,
+            jobs: Vec::new(),
+            foreground: None,
+            interrupted: false,
+            quit: false,
+            output_job: None,
+            next_id: 0,
+            vars: environment(),
+            aliases: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            positional: Vec::new(),
+            frames: Vec::new(),
+            loops: 0,
+            stdin: None,
+            subshell: None,
+            path_cache: PathCache::default(),
+            events_tx,
+            events_rx,
         }
     }
 }
+
+impl App {
+    /// The exit code of the last command, as seen by `$?`.
+    pub fn last_status(&self) -> i32 {
+        self.status.map_or(0, |status| status.code)
+    }
+}
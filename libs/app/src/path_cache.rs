@@ -0,0 +1,66 @@
+use std::os::unix::fs::PermissionsExt;
+use std::time::SystemTime;
+
+use crate::App;
+
+/// The executables of the `$PATH` directories, scanned again only when
+/// `PATH` changes or one of its directories is modified.
+#[derive(Default)]
+pub struct PathCache {
+    path: String,
+    mtimes: Vec<Option<SystemTime>>,
+    names: Vec<String>,
+}
+
+fn dirs(path: &str) -> impl Iterator<Item = &str> {
+    path.split(':').filter(|dir| !dir.is_empty())
+}
+
+fn mtime(dir: &str) -> Option<SystemTime> {
+    std::fs::metadata(dir).and_then(|md| md.modified()).ok()
+}
+
+impl PathCache {
+    fn is_stale(&self, path: &str) -> bool {
+        self.path != path || dirs(path).map(mtime).ne(self.mtimes.iter().copied())
+    }
+
+    fn scan(&mut self, path: &str) {
+        let mut names: Vec<String> = Vec::new();
+        for dir in dirs(path) {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let executable = std::fs::metadata(entry.path())
+                    .is_ok_and(|md| md.is_file() && md.permissions().mode() & 0o111 != 0);
+                if executable {
+                    names.push(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+
+        self.path = path.to_string();
+        self.mtimes = dirs(path).map(mtime).collect();
+        self.names = names;
+    }
+
+    /// The sorted names of every executable in the directories of `path`.
+    pub fn executables(&mut self, path: &str) -> &[String] {
+        if self.is_stale(path) {
+            self.scan(path);
+        }
+        &self.names
+    }
+}
+
+impl App {
+    /// The executables found on `$PATH`, from the cache when it is fresh.
+    pub fn path_executables(&mut self) -> &[String] {
+        let path = self.var("PATH").unwrap_or_default().to_string();
+        self.path_cache.executables(&path)
+    }
+}
@@ -0,0 +1,86 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+use crate::App;
+
+/// Points a match gains for every query character found.
+const MATCH: i64 = 16;
+/// Bonus for a character right after the previous match.
+const CONSECUTIVE: i64 = 8;
+/// Bonus for a character starting a word.
+const BOUNDARY: i64 = 8;
+/// Points the most recent entry gains over the oldest one.
+const RECENCY: i64 = 30;
+
+/// A history entry matching the search query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchMatch {
+    /// The index of the entry in `App.history`.
+    pub entry: usize,
+    /// The character positions of the command matched by the query.
+    pub positions: Vec<usize>,
+    pub score: i64,
+}
+
+/// Score `text` against `query`, whose characters must all appear in order.
+/// The search ignores case unless the query has an uppercase letter.
+/// Returns the score and the positions of the matched characters.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let smart_case = query.chars().any(|c| c.is_uppercase());
+    let fold = |c: char| match smart_case {
+        true => c,
+        false => c.to_lowercase().next().unwrap_or(c),
+    };
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut positions: Vec<usize> = Vec::new();
+    let mut score = 0;
+    let mut start = 0;
+    for q in query.chars().map(fold) {
+        let pos = start + chars[start..].iter().position(|c| fold(*c) == q)?;
+        score += MATCH;
+        match positions.last() {
+            Some(last) if *last + 1 == pos => score += CONSECUTIVE,
+            Some(last) => score -= (pos - last - 1).min(MATCH as usize) as i64,
+            None => score -= pos.min(MATCH as usize) as i64,
+        }
+        if pos == 0 || matches!(chars[pos - 1], ' ' | '/' | '-' | '_' | '.' | '|') {
+            score += BOUNDARY;
+        }
+        positions.push(pos);
+        start = pos + 1;
+    }
+    Some((score, positions))
+}
+
+impl App {
+    /// Fill `search_results` with the history entries matching
+    /// `search_query`, best first. Each command appears once, from its most
+    /// recent entry, and recent entries rank higher than old ones.
+    pub fn search_history(&mut self) {
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut results: Vec<SearchMatch> = Vec::new();
+        for (entry, item) in self.history.iter().enumerate().rev() {
+            if !seen.insert(&item.command) {
+                continue;
+            }
+            if let Some((score, positions)) = fuzzy_match(&self.search_query, &item.command) {
+                results.push(SearchMatch {
+                    entry,
+                    positions,
+                    score,
+                });
+            }
+        }
+
+        let count = results.len().max(1) as i64;
+        for (rank, result) in results.iter_mut().enumerate() {
+            result.score += RECENCY * (count - rank as i64) / count;
+        }
+        // A stable sort keeps the most recent first among equal scores.
+        results.sort_by_key(|result| Reverse(result.score));
+
+        self.search_results = results;
+        self.search_index = 0;
+    }
+}
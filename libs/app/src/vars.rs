@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use crate::App;
+
+/// A shell variable. Exported variables are passed to spawned commands.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variable {
+    pub value: String,
+    pub exported: bool,
+}
+
+/// A function call in progress: the positional parameters of its caller,
+/// and the variables it made local with their values before the call.
+#[derive(Clone, Debug, Default)]
+pub struct Frame {
+    positional: Vec<String>,
+    saved: Vec<(String, Option<Variable>)>,
+}
+
+/// The variables inherited from the environment, all exported.
+pub fn environment() -> BTreeMap<String, Variable> {
+    std::env::vars()
+        .map(|(name, value)| {
+            let variable = Variable {
+                value,
+                exported: true,
+            };
+            (name, variable)
+        })
+        .collect()
+}
+
+/// True if `name` can be assigned, as in `NAME=value`.
+pub fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+impl App {
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    /// Set a variable, keeping it exported if it already was.
+    pub fn set_var(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value,
+            None => {
+                let var = Variable {
+                    value,
+                    exported: false,
+                };
+                self.vars.insert(name.to_string(), var);
+            }
+        }
+    }
+
+    /// Mark a variable as exported, creating it empty if needed.
+    pub fn export_var(&mut self, name: &str) {
+        self.vars
+            .entry(name.to_string())
+            .or_insert(Variable {
+                value: String::new(),
+                exported: true,
+            })
+            .exported = true;
+    }
+
+    pub fn unset_var(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    /// The environment given to spawned commands.
+    pub fn exported_vars(&self) -> Vec<(String, String)> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name.clone(), var.value.clone()))
+            .collect()
+    }
+
+    /// Enter a function called with `args` as its positional parameters.
+    pub fn push_frame(&mut self, args: Vec<String>) {
+        let positional = std::mem::replace(&mut self.positional, args);
+        self.frames.push(Frame {
+            positional,
+            saved: Vec::new(),
+        });
+    }
+
+    /// Leave the innermost function, giving the caller its positional
+    /// parameters and the variables the function made local back.
+    pub fn pop_frame(&mut self) {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        self.positional = frame.positional;
+        for (name, var) in frame.saved.into_iter().rev() {
+            match var {
+                Some(var) => self.vars.insert(name, var),
+                None => self.vars.remove(&name),
+            };
+        }
+    }
+
+    /// Make `name` local to the innermost function, so that its value is
+    /// restored when the function returns. Returns false outside functions.
+    pub fn make_local(&mut self, name: &str) -> bool {
+        let frame = match self.frames.last_mut() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        if !frame.saved.iter().any(|(saved, _)| saved == name) {
+            frame
+                .saved
+                .push((name.to_string(), self.vars.get(name).cloned()));
+        }
+        true
+    }
+}
@@ -0,0 +1,590 @@
+use serde::Deserialize;
+
+use crate::App;
+
+/// The key bindings of the input line.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Keymap {
+    /// Editing mode takes emacs keys and Normal mode runs the shell
+    /// commands.
+    #[default]
+    Emacs,
+    /// Normal mode is the command mode of vi on the input line, and Editing
+    /// mode its insert mode.
+    Vi,
+}
+
+/// What the shell does after a key of vi command mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViAction {
+    /// Nothing, the command was run or waits for more keys.
+    None,
+    /// Switch to insert mode.
+    Insert,
+    /// Open the input line in `$EDITOR`.
+    Editor,
+    /// The key is not a vi command.
+    Unknown,
+}
+
+/// A vi command being typed, and the register.
+#[derive(Clone, Debug, Default)]
+pub struct ViState {
+    count: usize,
+    /// `d`, `c` or `y`, with the count typed before it.
+    operator: Option<(char, usize)>,
+    /// `f`, `t`, `F` or `T`, waiting for its character.
+    find: Option<char>,
+    /// `i` or `a` after an operator, waiting for the text object.
+    object: Option<char>,
+    /// The last `f`, `t`, `F` or `T` and its character, for `;` and `,`.
+    last_find: Option<(char, char)>,
+    /// The text last deleted or yanked, for `p` and `P`.
+    register: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Space,
+    Word,
+    Punctuation,
+}
+
+/// The class of `c` for word motions. Big words (`W`, `B`, `E`) are only
+/// separated by whitespace.
+fn class(c: char, big: bool) -> Class {
+    if c.is_whitespace() {
+        Class::Space
+    } else if big || c.is_alphanumeric() || c == '_' {
+        Class::Word
+    } else {
+        Class::Punctuation
+    }
+}
+
+/// The start of the next word after `i`.
+fn next_word(chars: &[char], mut i: usize, big: bool) -> usize {
+    let n = chars.len();
+    if i >= n {
+        return n;
+    }
+    let start = class(chars[i], big);
+    if start != Class::Space {
+        while i < n && class(chars[i], big) == start {
+            i += 1;
+        }
+    }
+    while i < n && class(chars[i], big) == Class::Space {
+        i += 1;
+    }
+    i
+}
+
+/// The last character of the word ending after `i`.
+fn word_end(chars: &[char], mut i: usize, big: bool) -> usize {
+    let n = chars.len();
+    i += 1;
+    while i < n && class(chars[i], big) == Class::Space {
+        i += 1;
+    }
+    if i >= n {
+        return n.saturating_sub(1);
+    }
+    let word = class(chars[i], big);
+    while i + 1 < n && class(chars[i + 1], big) == word {
+        i += 1;
+    }
+    i
+}
+
+/// The start of the word before `i`.
+fn previous_word(chars: &[char], mut i: usize, big: bool) -> usize {
+    if i == 0 {
+        return 0;
+    }
+    i -= 1;
+    while i > 0 && class(chars[i], big) == Class::Space {
+        i -= 1;
+    }
+    let word = class(chars[i], big);
+    while i > 0 && class(chars[i - 1], big) == word {
+        i -= 1;
+    }
+    i
+}
+
+/// Where `f`, `t`, `F` or `T` (`kind`) goes for the `count`th `target`.
+fn find(chars: &[char], i: usize, kind: char, target: char, count: usize) -> Option<usize> {
+    match kind {
+        'f' | 't' => {
+            let j = ((i + 1).min(chars.len())..chars.len())
+                .filter(|j| chars[*j] == target)
+                .nth(count - 1)?;
+            Some(if kind == 't' { j - 1 } else { j })
+        }
+        _ => {
+            let j = (0..i)
+                .rev()
+                .filter(|j| chars[*j] == target)
+                .nth(count - 1)?;
+            Some(if kind == 'T' { j + 1 } else { j })
+        }
+    }
+}
+
+/// The range of the text object `object` around `i`, inner (`i`) or with
+/// its delimiters (`a`).
+fn text_object(chars: &[char], i: usize, kind: char, object: char) -> Option<(usize, usize)> {
+    let n = chars.len();
+    if i >= n {
+        return None;
+    }
+    let pair = match object {
+        '(' | ')' | 'b' => Some(('(', ')')),
+        '[' | ']' => Some(('[', ']')),
+        '{' | '}' | 'B' => Some(('{', '}')),
+        '<' | '>' => Some(('<', '>')),
+        _ => None,
+    };
+
+    if let Some((open, close)) = pair {
+        let mut depth = 0;
+        let mut start = None;
+        for j in (0..=i).rev() {
+            if chars[j] == close && j != i {
+                depth += 1;
+            } else if chars[j] == open {
+                if depth == 0 {
+                    start = Some(j);
+                    break;
+                }
+                depth -= 1;
+            }
+        }
+        let start = start?;
+        let mut depth = 0;
+        let end = (start + 1..n).find(|j| {
+            if chars[*j] == open {
+                depth += 1;
+            } else if chars[*j] == close {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            false
+        })?;
+        return match kind {
+            'i' => Some((start + 1, end)),
+            _ => Some((start, end + 1)),
+        };
+    }
+
+    match object {
+        '"' | '\'' | '`' => {
+            let (start, end) = match (0..=i).rev().find(|j| chars[*j] == object) {
+                Some(start) => (start, (start + 1..n).find(|j| chars[*j] == object)?),
+                None => {
+                    let start = (i..n).find(|j| chars[*j] == object)?;
+                    (start, (start + 1..n).find(|j| chars[*j] == object)?)
+                }
+            };
+            match kind {
+                'i' => Some((start + 1, end)),
+                _ => Some((start, end + 1)),
+            }
+        }
+        'w' | 'W' => {
+            let big = object == 'W';
+            let word = class(chars[i], big);
+            let mut start = i;
+            while start > 0 && class(chars[start - 1], big) == word {
+                start -= 1;
+            }
+            let mut end = i + 1;
+            while end < n && class(chars[end], big) == word {
+                end += 1;
+            }
+            if kind == 'a' {
+                // The whitespace after the word, or else the one before it.
+                let mut after = end;
+                while after < n && class(chars[after], big) == Class::Space {
+                    after += 1;
+                }
+                if after > end {
+                    end = after;
+                } else {
+                    while start > 0 && class(chars[start - 1], big) == Class::Space {
+                        start -= 1;
+                    }
+                }
+            }
+            Some((start, end))
+        }
+        _ => None,
+    }
+}
+
+impl App {
+    fn chars(&self) -> Vec<char> {
+        self.input.chars().collect()
+    }
+
+    /// The cursor as a character index.
+    fn char_cursor(&self) -> usize {
+        self.input[..self.editor.cursor].chars().count()
+    }
+
+    fn set_char_cursor(&mut self, i: usize) {
+        self.editor.cursor = self
+            .input
+            .char_indices()
+            .nth(i)
+            .map_or(self.input.len(), |(pos, _)| pos);
+    }
+
+    /// Keep the cursor on a character, as vi does in command mode.
+    pub fn vi_clamp(&mut self) {
+        let last = self.input.chars().count().saturating_sub(1);
+        if self.char_cursor() > last {
+            self.set_char_cursor(last);
+        }
+    }
+
+    /// Where the motion `key` goes from the cursor, `count` times, and
+    /// whether the character there is part of the range of an operator.
+    fn vi_motion(&mut self, key: char, count: usize) -> Option<(usize, bool)> {
+        let chars = self.chars();
+        let n = chars.len();
+        let i = self.char_cursor();
+        let repeat = |f: &dyn Fn(usize) -> usize| (0..count).fold(i, |j, _| f(j));
+        match key {
+            'h' => Some((i.saturating_sub(count), false)),
+            'l' => Some(((i + count).min(n), false)),
+            '0' => Some((0, false)),
+            '^' => Some((
+                chars.iter().position(|c| !c.is_whitespace()).unwrap_or(n),
+                false,
+            )),
+            '$' => Some((n, false)),
+            'w' | 'W' => Some((repeat(&|j| next_word(&chars, j, key == 'W')), false)),
+            'b' | 'B' => Some((repeat(&|j| previous_word(&chars, j, key == 'B')), false)),
+            'e' | 'E' => Some((repeat(&|j| word_end(&chars, j, key == 'E')), true)),
+            ';' | ',' => {
+                let (kind, target) = self.vi.last_find?;
+                let kind = match key {
+                    ';' => kind,
+                    _ => match kind {
+                        'f' => 'F',
+                        'F' => 'f',
+                        't' => 'T',
+                        _ => 't',
+                    },
+                };
+                // Repeating `t` or `T` from just before the target goes on
+                // to the next one instead of staying in place.
+                let j = match find(&chars, i, kind, target, count)? {
+                    j if j == i && matches!(kind, 't' | 'T') => {
+                        find(&chars, i, kind, target, count + 1)?
+                    }
+                    j => j,
+                };
+                Some((j, kind == 'f' || kind == 't'))
+            }
+            _ => None,
+        }
+    }
+
+    /// Apply `operator` to the characters `start..end`, or move the cursor
+    /// to `start` when there is none.
+    fn vi_apply(&mut self, operator: Option<char>, start: usize, end: usize) -> ViAction {
+        let operator = match operator {
+            Some(operator) => operator,
+            None => {
+                self.set_char_cursor(start);
+                self.vi_clamp();
+                return ViAction::None;
+            }
+        };
+        let chars = self.chars();
+        let end = end.min(chars.len());
+        let text: String = chars[start..end].iter().collect();
+        self.vi.register = text;
+        if operator != 'y' {
+            self.save_undo();
+            self.input = chars[..start].iter().chain(&chars[end..]).collect();
+        }
+        self.set_char_cursor(start);
+        match operator {
+            'c' => ViAction::Insert,
+            _ => {
+                self.vi_clamp();
+                ViAction::None
+            }
+        }
+    }
+
+    /// Run the motion `key` after the pending operator, if any.
+    fn vi_move(&mut self, key: char, count: usize, operator: Option<char>) -> ViAction {
+        let chars = self.chars();
+        let i = self.char_cursor();
+        // `cw` changes up to the end of the word, like `ce`.
+        let key = match (operator, key) {
+            (Some('c'), 'w' | 'W') if chars.get(i).is_some_and(|c| !c.is_whitespace()) => {
+                if key == 'w' {
+                    'e'
+                } else {
+                    'E'
+                }
+            }
+            _ => key,
+        };
+        let (target, inclusive) = match self.vi_motion(key, count) {
+            Some(motion) => motion,
+            None => return ViAction::None,
+        };
+        match operator {
+            None => self.vi_apply(None, target, target),
+            Some(_) if target >= i => self.vi_apply(operator, i, target + inclusive as usize),
+            Some(_) => self.vi_apply(operator, target, i),
+        }
+    }
+
+    fn vi_paste(&mut self, after: bool, count: usize) {
+        if self.vi.register.is_empty() {
+            return;
+        }
+        self.save_undo();
+        if after {
+            self.move_right();
+        }
+        let text = self.vi.register.repeat(count);
+        self.input.insert_str(self.editor.cursor, &text);
+        self.editor.cursor += text.len();
+        let last = self.char_cursor().saturating_sub(1);
+        self.set_char_cursor(last);
+    }
+
+    /// Forget the vi command being typed.
+    pub fn vi_reset(&mut self) {
+        self.vi.count = 0;
+        self.vi.operator = None;
+        self.vi.find = None;
+        self.vi.object = None;
+    }
+
+    /// Run the key `key` of vi command mode.
+    pub fn vi_key(&mut self, key: char) -> ViAction {
+        let count = self.vi.count.max(1);
+        let (operator, total) = match self.vi.operator {
+            Some((operator, op_count)) => (Some(operator), op_count * count),
+            None => (None, count),
+        };
+
+        if let Some(kind) = self.vi.find.take() {
+            self.vi_reset();
+            self.vi.last_find = Some((kind, key));
+            let chars = self.chars();
+            let i = self.char_cursor();
+            return match find(&chars, i, kind, key, total) {
+                Some(j) if operator.is_none() => self.vi_apply(None, j, j),
+                Some(j) if j >= i => self.vi_apply(operator, i, j + 1),
+                Some(j) => self.vi_apply(operator, j, i),
+                None => ViAction::None,
+            };
+        }
+        if let Some(kind) = self.vi.object.take() {
+            self.vi_reset();
+            let chars = self.chars();
+            return match text_object(&chars, self.char_cursor(), kind, key) {
+                Some((start, end)) => self.vi_apply(operator, start, end),
+                None => ViAction::None,
+            };
+        }
+
+        match key {
+            '1'..='9' => {
+                self.vi.count = self.vi.count * 10 + key.to_digit(10).unwrap_or(0) as usize;
+                return ViAction::None;
+            }
+            '0' if self.vi.count > 0 => {
+                self.vi.count *= 10;
+                return ViAction::None;
+            }
+            'f' | 't' | 'F' | 'T' => {
+                self.vi.find = Some(key);
+                return ViAction::None;
+            }
+            'i' | 'a' if operator.is_some() => {
+                self.vi.object = Some(key);
+                return ViAction::None;
+            }
+            'd' | 'c' | 'y' => {
+                self.vi.count = 0;
+                if operator == Some(key) {
+                    // `dd`, `cc` and `yy` act on the whole line.
+                    self.vi_reset();
+                    let n = self.input.chars().count();
+                    return self.vi_apply(Some(key), 0, n);
+                }
+                self.vi.operator = Some((key, count));
+                return ViAction::None;
+            }
+            _ => {}
+        }
+
+        self.vi_reset();
+        match key {
+            'h' | 'l' | 'w' | 'W' | 'b' | 'B' | 'e' | 'E' | '0' | '^' | '$' | ';' | ',' => {
+                self.vi_move(key, total, operator)
+            }
+            _ if operator.is_some() => ViAction::None,
+            'x' => self.vi_move('l', total, Some('d')),
+            'X' => self.vi_move('h', total, Some('d')),
+            'D' => self.vi_move('$', 1, Some('d')),
+            'C' => self.vi_move('$', 1, Some('c')),
+            'p' | 'P' => {
+                self.vi_paste(key == 'p', total);
+                ViAction::None
+            }
+            'u' => {
+                for _ in 0..total {
+                    self.undo();
+                }
+                self.vi_clamp();
+                ViAction::None
+            }
+            'i' => ViAction::Insert,
+            'a' => {
+                if !self.input.is_empty() {
+                    self.move_right();
+                }
+                ViAction::Insert
+            }
+            'A' => {
+                self.move_end();
+                ViAction::Insert
+            }
+            'I' => {
+                let first = self.input.len() - self.input.trim_start().len();
+                self.editor.cursor = first;
+                ViAction::Insert
+            }
+            'v' => ViAction::Editor,
+            _ => ViAction::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    /// The line and the cursor after typing `keys` in command mode on
+    /// `input`, with the cursor on character `cursor`.
+    fn type_keys(input: &str, cursor: usize, keys: &str) -> (String, usize) {
+        let mut app = App {
+            input: input.to_string(),
+            ..App::default()
+        };
+        app.set_char_cursor(cursor);
+        for key in keys.chars() {
+            app.vi_key(key);
+        }
+        (app.input.clone(), app.char_cursor())
+    }
+
+    #[test]
+    fn word_motions() {
+        let line = chars("git commit --amend -m x");
+        assert_eq!(next_word(&line, 0, false), 4);
+        assert_eq!(next_word(&line, 4, false), 11);
+        assert_eq!(next_word(&line, 11, false), 13);
+        assert_eq!(next_word(&line, 11, true), 19);
+        assert_eq!(next_word(&line, 22, false), 23);
+        assert_eq!(word_end(&line, 0, false), 2);
+        assert_eq!(word_end(&line, 2, false), 9);
+        assert_eq!(word_end(&line, 10, false), 12);
+        assert_eq!(word_end(&line, 10, true), 17);
+        assert_eq!(word_end(&line, 22, false), 22);
+        assert_eq!(previous_word(&line, 4, false), 0);
+        assert_eq!(previous_word(&line, 13, false), 11);
+        assert_eq!(previous_word(&line, 18, true), 11);
+        assert_eq!(previous_word(&line, 0, false), 0);
+    }
+
+    #[test]
+    fn find_motions() {
+        let line = chars("a,b,c,d");
+        assert_eq!(find(&line, 0, 'f', ',', 1), Some(1));
+        assert_eq!(find(&line, 0, 'f', ',', 3), Some(5));
+        assert_eq!(find(&line, 0, 'f', ',', 4), None);
+        assert_eq!(find(&line, 0, 't', ',', 2), Some(2));
+        assert_eq!(find(&line, 0, 't', ',', 1), Some(0));
+        assert_eq!(find(&line, 0, 't', 'b', 1), Some(1));
+        assert_eq!(find(&line, 6, 'F', ',', 1), Some(5));
+        assert_eq!(find(&line, 6, 'T', ',', 2), Some(4));
+        assert_eq!(find(&line, 0, 'F', ',', 1), None);
+    }
+
+    #[test]
+    fn text_objects() {
+        let line = chars("echo (a (b) c) \"x y\" end");
+        assert_eq!(text_object(&line, 6, 'i', '('), Some((6, 13)));
+        assert_eq!(text_object(&line, 6, 'a', 'b'), Some((5, 14)));
+        assert_eq!(text_object(&line, 9, 'i', ')'), Some((9, 10)));
+        assert_eq!(text_object(&line, 17, 'i', '"'), Some((16, 19)));
+        assert_eq!(text_object(&line, 17, 'a', '"'), Some((15, 20)));
+        // Before the quotes, the next quoted text is used.
+        assert_eq!(text_object(&line, 0, 'i', '"'), Some((16, 19)));
+        assert_eq!(text_object(&line, 1, 'i', 'w'), Some((0, 4)));
+        assert_eq!(text_object(&line, 1, 'a', 'w'), Some((0, 5)));
+        // At the end of the line, `aw` takes the whitespace before.
+        assert_eq!(text_object(&line, 22, 'a', 'w'), Some((20, 24)));
+        assert_eq!(text_object(&line, 0, 'i', '['), None);
+        assert_eq!(text_object(&line, 30, 'i', 'w'), None);
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(type_keys("one two three", 0, "dw"), ("two three".into(), 0));
+        assert_eq!(type_keys("one two three", 0, "2dw"), ("three".into(), 0));
+        assert_eq!(type_keys("one two three", 0, "d2w"), ("three".into(), 0));
+        assert_eq!(
+            type_keys("one two three", 4, "de"),
+            ("one  three".into(), 4)
+        );
+        assert_eq!(type_keys("one two three", 4, "db"), ("two three".into(), 0));
+        assert_eq!(type_keys("one two three", 4, "d$"), ("one ".into(), 3));
+        assert_eq!(type_keys("one two three", 4, "dd"), ("".into(), 0));
+        assert_eq!(type_keys("one two three", 4, "dfe"), ("one e".into(), 4));
+        assert_eq!(type_keys("one two three", 4, "dte"), ("one ee".into(), 4));
+        assert_eq!(
+            type_keys("one two three", 0, "3x"),
+            (" two three".into(), 0)
+        );
+        assert_eq!(
+            type_keys("one two three", 5, "X"),
+            ("one wo three".into(), 4)
+        );
+        assert_eq!(type_keys("f(a, b)", 3, "di("), ("f()".into(), 2));
+        // `;` after `t` goes on past the target next to the cursor.
+        assert_eq!(type_keys("a,b,c", 0, "t,;"), ("a,b,c".into(), 2));
+        assert_eq!(type_keys("a,b,c", 0, "2t,"), ("a,b,c".into(), 2));
+    }
+
+    #[test]
+    fn change_and_yank() {
+        // `cw` on a word stops at its end, like `ce`.
+        assert_eq!(type_keys("one two", 0, "cw"), (" two".into(), 0));
+        assert_eq!(type_keys("one two", 0, "yyP"), ("one twoone two".into(), 6));
+        assert_eq!(type_keys("one two", 0, "ywP"), ("one one two".into(), 3));
+        assert_eq!(type_keys("one two", 0, "dwp"), ("tone wo".into(), 4));
+        assert_eq!(type_keys("one two", 0, "dwu"), ("one two".into(), 0));
+    }
+}
@@ -0,0 +1,879 @@
+use lib_app::*;
+
+use crate::exec::signal_job;
+use crate::expand::*;
+use crate::parser::*;
+
+use std::ffi::CString;
+use std::io;
+
+/// Commands run inside the shell instead of being spawned.
+pub const BUILTINS: [&str; 24] = [
+    "alias", "bg", "break", "c", "cd", "continue", "dirs", "env", "exit", "export", "fg", "help",
+    "history", "jobs", "kill", "local", "popd", "pushd", "return", "set", "shift", "unalias",
+    "unset", "wait",
+];
+
+/// What the shell does after running a builtin.
+pub enum Builtin {
+    Status(i32),
+    /// Wait for these jobs to finish, as `fg` and `wait` do. The status is
+    /// the last job's one, or 0 when `last` is false.
+    Wait {
+        jobs: Vec<usize>,
+        last: bool,
+    },
+    /// Leave the running function with this status.
+    Return(i32),
+    /// Leave the `n`th enclosing loop.
+    Break(usize),
+    /// Go on with the next iteration of the `n`th enclosing loop.
+    Continue(usize),
+    /// Exit the shell with this status.
+    Exit(i32),
+}
+
+/// A `jobs` line such as `[1]  Running    sleep 10`.
+pub fn job_line(job: &Job, long: bool) -> String {
+    let state = match job.state {
+        JobState::Done {
+            signal: Some(signal),
+            ..
+        } => signal_name(signal),
+        JobState::Done { code, .. } if code != 0 => format!("Exit {}", code),
+        _ => job.state_name().to_string(),
+    };
+    let command = escape_markup(&job.command);
+    match long {
+        true => format!(
+            "[{}]  {:<7} {:<10} {}",
+            job.number, job.pgid, state, command
+        ),
+        false => format!("[{}]  {:<10} {}", job.number, state, command),
+    }
+}
+
+fn error(app: &mut App, message: String) -> i32 {
+    app.output
+        .push_str(&format!("<c>{}</c>\n", escape_markup(&message)));
+    1
+}
+
+/// `value` quoted so that the shell would read it back unchanged.
+pub fn quote(value: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !value.is_empty() && value.chars().all(plain) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// `path` joined to `base`, with `.` and `..` components resolved without
+/// following symlinks, as `cd` does by default.
+fn logical_path(base: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    let joined = match path.starts_with('/') {
+        true => path.to_string(),
+        false => format!("{}/{}", base, path),
+    };
+    for component in joined.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    format!("/{}", components.join("/"))
+}
+
+/// The directory `cd` goes to for `dir`, and whether it was found through
+/// `CDPATH` or `-` and should be printed.
+fn cd_target(dir: &str, app: &App) -> Result<(String, bool), String> {
+    if dir == "-" {
+        return match app.var("OLDPWD") {
+            Some(old) => Ok((old.to_string(), true)),
+            None => Err(String::from("cd: OLDPWD not set")),
+        };
+    }
+
+    let relative = !dir.starts_with('/') && dir != "." && dir != "..";
+    let relative = relative && !dir.starts_with("./") && !dir.starts_with("../");
+    if relative {
+        let cdpath = app.var("CDPATH").unwrap_or_default();
+        for entry in cdpath.split(':').filter(|entry| !entry.is_empty()) {
+            let candidate = format!("{}/{}", logical_path(&app.path, entry), dir);
+            if std::fs::metadata(&candidate).is_ok_and(|md| md.is_dir()) {
+                return Ok((logical_path(&candidate, ""), true));
+            }
+        }
+    }
+    Ok((logical_path(&app.path, dir), false))
+}
+
+fn cd(args: &[String], app: &mut App) -> i32 {
+    let dir = match args {
+        [] => match app.var("HOME") {
+            Some(home) => home.to_string(),
+            None => return error(app, String::from("cd: HOME not set")),
+        },
+        [dir] => dir.clone(),
+        _ => return error(app, String::from("cd: too many arguments")),
+    };
+
+    let (path, print) = match cd_target(&dir, app) {
+        Ok(target) => target,
+        Err(message) => return error(app, message),
+    };
+    if let Err(reason) = change_dir(&path, app) {
+        return error(app, format!("cd: {}: {}", dir, reason));
+    }
+    if print {
+        app.output.push_str(&format!("{}\n", escape_markup(&path)));
+    }
+    0
+}
+
+/// Make `path` the current directory and update `PWD` and `OLDPWD`.
+/// The error is the reason the directory could not be entered.
+pub fn change_dir(path: &str, app: &mut App) -> Result<(), String> {
+    // A subshell runs in the shell's process, so it only keeps its own
+    // directory, which must still be one that can be entered.
+    let entered = match app.subshell {
+        Some(_) => enter_dir(path),
+        None => std::env::set_current_dir(path),
+    };
+    if let Err(err) = entered {
+        let message = err.to_string();
+        let reason = message.split(" (os error").next().unwrap_or_default();
+        return Err(reason.to_string());
+    }
+
+    let old = std::mem::replace(&mut app.path, path.to_string());
+    app.set_var("OLDPWD", old);
+    app.export_var("OLDPWD");
+    app.set_var("PWD", path.to_string());
+    app.export_var("PWD");
+    Ok(())
+}
+
+/// Check that `path` is a directory that can be entered, without entering it.
+fn enter_dir(path: &str) -> io::Result<()> {
+    if !std::fs::metadata(path)?.is_dir() {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+    let path = CString::new(path).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    match unsafe { libc::access(path.as_ptr(), libc::X_OK) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn print_dirs(app: &mut App) {
+    let dirs: Vec<String> = app.dirs().iter().map(|dir| app.tilde(dir)).collect();
+    app.output
+        .push_str(&escape_markup(&format!("{}\n", dirs.join(" "))));
+}
+
+fn is_stack_index(arg: &str) -> bool {
+    let digits = arg.get(1..).unwrap_or_default();
+    (arg.starts_with('+') || arg.starts_with('-'))
+        && !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The position in `dirs` named by `+n` (from the left, `dirs -v` numbers)
+/// or `-n` (from the right).
+fn stack_index(name: &str, arg: &str, app: &mut App) -> Option<usize> {
+    let len = app.dirs().len();
+    match arg[1..].parse::<usize>() {
+        Ok(n) if n < len && arg.starts_with('+') => Some(n),
+        Ok(n) if n < len => Some(len - 1 - n),
+        _ => {
+            error(
+                app,
+                format!("{}: {}: directory stack index out of range", name, arg),
+            );
+            None
+        }
+    }
+}
+
+/// Enter the first directory of `dirs` and keep the others as the stack.
+fn set_dirs(name: &str, dirs: Vec<String>, app: &mut App) -> i32 {
+    if dirs[0] != app.path {
+        if let Err(reason) = change_dir(&dirs[0], app) {
+            return error(app, format!("{}: {}: {}", name, dirs[0], reason));
+        }
+    }
+    app.dir_stack = dirs[1..].to_vec();
+    print_dirs(app);
+    0
+}
+
+/// Rotate the stack so that entry `n` of `dirs -v` becomes the current
+/// directory, as `pushd +n` does.
+pub fn rotate_dirs(n: usize, app: &mut App) -> i32 {
+    let mut dirs = app.dirs();
+    let len = dirs.len();
+    dirs.rotate_left(n % len);
+    set_dirs("pushd", dirs, app)
+}
+
+fn pushd(args: &[String], app: &mut App) -> i32 {
+    let mut dirs = app.dirs();
+    match args {
+        [] if dirs.len() < 2 => error(app, String::from("pushd: no other directory")),
+        [] => {
+            dirs.swap(0, 1);
+            set_dirs("pushd", dirs, app)
+        }
+        [arg] if is_stack_index(arg) => match stack_index("pushd", arg, app) {
+            Some(n) => rotate_dirs(n, app),
+            None => 1,
+        },
+        [dir] => {
+            let path = match cd_target(dir, app) {
+                Ok((path, _)) => path,
+                Err(message) => return error(app, message.replacen("cd", "pushd", 1)),
+            };
+            if let Err(reason) = change_dir(&path, app) {
+                return error(app, format!("pushd: {}: {}", dir, reason));
+            }
+            dirs.insert(0, path);
+            set_dirs("pushd", dirs, app)
+        }
+        _ => error(app, String::from("pushd: too many arguments")),
+    }
+}
+
+fn popd(args: &[String], app: &mut App) -> i32 {
+    let mut dirs = app.dirs();
+    if dirs.len() < 2 {
+        return error(app, String::from("popd: directory stack empty"));
+    }
+    let n = match args {
+        [] => 0,
+        [arg] if is_stack_index(arg) => match stack_index("popd", arg, app) {
+            Some(n) => n,
+            None => return 1,
+        },
+        [arg] => return error(app, format!("popd: {}: invalid argument", arg)),
+        _ => return error(app, String::from("popd: too many arguments")),
+    };
+    dirs.remove(n);
+    set_dirs("popd", dirs, app)
+}
+
+fn dirs(args: &[String], app: &mut App) -> i32 {
+    let (mut numbered, mut per_line, mut long) = (false, false, false);
+    let mut index = None;
+    for arg in args.iter() {
+        if is_stack_index(arg) {
+            index = match stack_index("dirs", arg, app) {
+                Some(n) => Some(n),
+                None => return 1,
+            };
+            continue;
+        }
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && flags.chars().all(|c| "clpv".contains(c)) => {
+                if flags.contains('c') {
+                    app.dir_stack.clear();
+                    return 0;
+                }
+                numbered |= flags.contains('v');
+                per_line |= flags.contains('p');
+                long |= flags.contains('l');
+            }
+            _ => return error(app, String::from("dirs: usage: dirs [-clpv] [+n|-n]")),
+        }
+    }
+
+    let dirs: Vec<String> = app
+        .dirs()
+        .iter()
+        .map(|dir| if long { dir.clone() } else { app.tilde(dir) })
+        .collect();
+    let text = match index {
+        Some(n) => format!("{}\n", dirs[n]),
+        None if numbered => dirs
+            .iter()
+            .enumerate()
+            .map(|(n, dir)| format!("{:>2}  {}\n", n, dir))
+            .collect(),
+        None if per_line => dirs.iter().map(|dir| format!("{}\n", dir)).collect(),
+        None => format!("{}\n", dirs.join(" ")),
+    };
+    app.output.push_str(&escape_markup(&text));
+    0
+}
+
+fn set(args: &[String], app: &mut App) -> i32 {
+    match args {
+        [] => {
+            for (name, var) in app.vars.iter() {
+                app.output
+                    .push_str(&escape_markup(&format!("{}={}\n", name, quote(&var.value))));
+            }
+            0
+        }
+        [flag] if flag == "-o" || flag == "+o" => {
+            let options = [
+                ("emacs", app.keymap == Keymap::Emacs),
+                ("pipefail", app.pipefail),
+                ("vi", app.keymap == Keymap::Vi),
+            ];
+            for (option, on) in options {
+                app.output.push_str(&match flag.as_str() {
+                    "-o" => format!("{:<15}{}\n", option, if on { "on" } else { "off" }),
+                    _ => format!("set {}o {}\n", if on { "-" } else { "+" }, option),
+                });
+            }
+            0
+        }
+        [flag, option] if option == "pipefail" && (flag == "-o" || flag == "+o") => {
+            app.pipefail = flag == "-o";
+            0
+        }
+        // Turning one keymap off turns the other one on.
+        [flag, option]
+            if (option == "vi" || option == "emacs") && (flag == "-o" || flag == "+o") =>
+        {
+            let vi = (option == "vi") == (flag == "-o");
+            app.keymap = if vi { Keymap::Vi } else { Keymap::Emacs };
+            app.vi_reset();
+            0
+        }
+        _ => {
+            app.output
+                .push_str("<c>set: usage: set [-o|+o pipefail|vi|emacs]</c>\n");
+            2
+        }
+    }
+}
+
+fn export(args: &[String], app: &mut App) -> i32 {
+    let (unexport, names) = match args.first().map(|s| s.as_str()) {
+        Some("-n") => (true, &args[1..]),
+        Some("-p") => (false, &args[1..]),
+        _ => (false, args),
+    };
+
+    if names.is_empty() {
+        let lines: Vec<String> = app
+            .vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| escape_markup(&format!("export {}={}\n", name, quote(&var.value))))
+            .collect();
+        app.output.push_str(&lines.concat());
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in names.iter() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_var_name(name) {
+            status = error(app, format!("export: `{}': not a valid identifier", arg));
+            continue;
+        }
+        if let Some(value) = value {
+            app.set_var(name, value.to_string());
+        }
+        match app.vars.get_mut(name) {
+            Some(var) if unexport => var.exported = false,
+            _ if unexport => {}
+            _ => app.export_var(name),
+        }
+    }
+    status
+}
+
+fn unset(args: &[String], app: &mut App) -> i32 {
+    let (functions, names) = match args.first().map(|s| s.as_str()) {
+        Some("-f") => (true, &args[1..]),
+        Some("-v") => (false, &args[1..]),
+        _ => (false, args),
+    };
+    let mut status = 0;
+    for name in names.iter() {
+        if functions {
+            app.functions.remove(name);
+            continue;
+        }
+        match is_var_name(name) {
+            true => app.unset_var(name),
+            false => status = error(app, format!("unset: `{}': not a valid identifier", name)),
+        }
+    }
+    status
+}
+
+fn local(args: &[String], app: &mut App) -> i32 {
+    if app.frames.is_empty() {
+        return error(app, String::from("local: can only be used in a function"));
+    }
+    let mut status = 0;
+    for arg in args.iter() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_var_name(name) {
+            status = error(app, format!("local: `{}': not a valid identifier", arg));
+            continue;
+        }
+        app.make_local(name);
+        match value {
+            Some(value) => app.set_var(name, value.to_string()),
+            None => app.unset_var(name),
+        }
+    }
+    status
+}
+
+fn return_builtin(args: &[String], app: &mut App) -> Builtin {
+    if app.frames.is_empty() {
+        let message = String::from("return: can only `return' from a function");
+        return Builtin::Status(error(app, message));
+    }
+    match args {
+        [] => Builtin::Return(app.last_status()),
+        [n] => match n.parse::<i32>() {
+            Ok(n) => Builtin::Return(n & 0xff),
+            Err(_) => {
+                error(app, format!("return: {}: numeric argument required", n));
+                Builtin::Return(2)
+            }
+        },
+        _ => Builtin::Status(error(app, String::from("return: too many arguments"))),
+    }
+}
+
+/// `break [n]` or `continue [n]` (`name`), for the `n`th enclosing loop or
+/// the outermost one when there are fewer.
+fn loop_builtin(name: &str, args: &[String], app: &mut App) -> Builtin {
+    let n = match args {
+        [] => 1,
+        [n] => match n.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                let message = format!("{}: {}: loop count out of range", name, n);
+                return Builtin::Status(error(app, message));
+            }
+        },
+        _ => return Builtin::Status(error(app, format!("{}: too many arguments", name))),
+    };
+    if app.loops == 0 {
+        let message = format!(
+            "{}: only meaningful in a `for', `while', or `until' loop",
+            name
+        );
+        return Builtin::Status(error(app, message));
+    }
+    match name {
+        "break" => Builtin::Break(n.min(app.loops)),
+        _ => Builtin::Continue(n.min(app.loops)),
+    }
+}
+
+fn shift(args: &[String], app: &mut App) -> i32 {
+    let n = match args {
+        [] => 1,
+        [n] => match n.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => return error(app, format!("shift: {}: numeric argument required", n)),
+        },
+        _ => return error(app, String::from("shift: too many arguments")),
+    };
+    if n > app.positional.len() {
+        return error(app, format!("shift: {}: shift count out of range", n));
+    }
+    app.positional.drain(..n);
+    0
+}
+
+fn exit(args: &[String], app: &mut App) -> Builtin {
+    match args {
+        [] => Builtin::Exit(app.last_status()),
+        [n] => match n.parse::<i32>() {
+            Ok(n) => Builtin::Exit(n & 0xff),
+            Err(_) => {
+                error(app, format!("exit: {}: numeric argument required", n));
+                Builtin::Exit(2)
+            }
+        },
+        _ => Builtin::Status(error(app, String::from("exit: too many arguments"))),
+    }
+}
+
+/// Whether `name` can be an alias: a word the parser reads back as is.
+fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || "|&;<>()$`\\\"'=/".contains(c))
+}
+
+fn alias(args: &[String], app: &mut App) -> i32 {
+    let names = match args.first().map(|s| s.as_str()) {
+        Some("-p") => &args[1..],
+        _ => args,
+    };
+
+    if names.is_empty() {
+        let lines: Vec<String> = app
+            .aliases
+            .iter()
+            .map(|(name, value)| escape_markup(&format!("alias {}={}\n", name, quote(value))))
+            .collect();
+        app.output.push_str(&lines.concat());
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in names.iter() {
+        match arg.split_once('=') {
+            Some((name, value)) if is_alias_name(name) => {
+                app.aliases.insert(name.to_string(), value.to_string());
+            }
+            Some(_) => status = error(app, format!("alias: `{}': invalid alias name", arg)),
+            None => match app.aliases.get(arg.as_str()) {
+                Some(value) => {
+                    let line = escape_markup(&format!("alias {}={}\n", arg, quote(value)));
+                    app.output.push_str(&line);
+                }
+                None => status = error(app, format!("alias: {}: not found", arg)),
+            },
+        }
+    }
+    status
+}
+
+fn unalias(args: &[String], app: &mut App) -> i32 {
+    if args.first().map(|s| s.as_str()) == Some("-a") {
+        app.aliases.clear();
+        return 0;
+    }
+    if args.is_empty() {
+        app.output
+            .push_str("<c>unalias: usage: unalias [-a] name [name ...]</c>\n");
+        return 2;
+    }
+    let mut status = 0;
+    for name in args.iter() {
+        if app.aliases.remove(name).is_none() {
+            status = error(app, format!("unalias: {}: not found", name));
+        }
+    }
+    status
+}
+
+fn env(app: &mut App) -> i32 {
+    for (name, value) in app.exported_vars() {
+        app.output
+            .push_str(&escape_markup(&format!("{}={}\n", name, value)));
+    }
+    0
+}
+
+fn history(args: &[String], app: &mut App) -> i32 {
+    let count = match args {
+        [] => app.history.len(),
+        [flag] if flag == "-c" => {
+            app.history.clear();
+            app.history_pending = false;
+            return 0;
+        }
+        [n] => match n.parse::<usize>() {
+            Ok(n) => n.min(app.history.len()),
+            Err(_) => return error(app, format!("history: {}: numeric argument required", n)),
+        },
+        _ => return error(app, String::from("history: usage: history [-c] [n]")),
+    };
+
+    let start = app.history.len() - count;
+    let lines: String = app.history[start..]
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            format!(
+                "<i>{:>5}</i>  {}\n",
+                start + i + 1,
+                escape_markup(&entry.command)
+            )
+        })
+        .collect();
+    app.output.push_str(&lines);
+    0
+}
+
+fn jobs(args: &[String], app: &mut App) -> i32 {
+    let long = args.iter().any(|arg| arg == "-l");
+    let lines: Vec<String> = app.jobs.iter().map(|job| job_line(job, long)).collect();
+    for line in lines {
+        app.output.push_str(&format!("<i>{}</i>\n", line));
+    }
+
+    // Finished jobs have now been reported.
+    let done: Vec<usize> = app
+        .jobs
+        .iter()
+        .filter(|job| job.is_done())
+        .map(|job| job.number)
+        .collect();
+    for number in done {
+        app.remove_job(number);
+    }
+    0
+}
+
+/// The job named by the first argument, or the current job.
+fn job_arg(name: &str, args: &[String], app: &mut App) -> Option<usize> {
+    let number = match args.first() {
+        Some(spec) => app.find_job(spec),
+        None => app.current_job(),
+    };
+    if number.is_none() {
+        let spec = args.first().map_or("current", |s| s.as_str());
+        error(app, format!("{}: {}: no such job", name, spec));
+    }
+    number
+}
+
+fn continue_job(job: &mut Job) {
+    if job.state == JobState::Stopped {
+        signal_job(job, libc::SIGCONT);
+    }
+    job.state = JobState::Running;
+}
+
+fn fg(args: &[String], app: &mut App) -> Builtin {
+    let number = match job_arg("fg", args, app) {
+        Some(number) => number,
+        None => return Builtin::Status(1),
+    };
+    let job = app.job_mut(number).unwrap();
+    if job.is_done() {
+        return Builtin::Wait {
+            jobs: vec![number],
+            last: true,
+        };
+    }
+    continue_job(job);
+
+    app.output = format!("<i>{}</i>\n{}", escape_markup(&job.command), job.output);
+    app.foreground = Some(number);
+    Builtin::Wait {
+        jobs: vec![number],
+        last: true,
+    }
+}
+
+fn bg(args: &[String], app: &mut App) -> i32 {
+    let number = match job_arg("bg", args, app) {
+        Some(number) => number,
+        None => return 1,
+    };
+    let job = app.job_mut(number).unwrap();
+    if job.is_done() {
+        return error(app, format!("bg: job {} has already completed", number));
+    }
+    continue_job(job);
+
+    let line = format!(
+        "<i>[{}]  {} &</i>\n",
+        job.number,
+        escape_markup(&job.command)
+    );
+    app.output.push_str(&line);
+    0
+}
+
+fn kill(args: &[String], app: &mut App) -> i32 {
+    let mut signal = libc::SIGTERM;
+    let mut targets = args;
+
+    match args {
+        [flag, name, rest @ ..] if flag == "-s" => {
+            signal = match signal_number(name) {
+                Some(signal) => signal,
+                None => return error(app, format!("kill: {}: invalid signal", name)),
+            };
+            targets = rest;
+        }
+        [flag, rest @ ..] if flag.starts_with('-') && flag.len() > 1 => {
+            signal = match signal_number(&flag[1..]) {
+                Some(signal) => signal,
+                None => return error(app, format!("kill: {}: invalid signal", &flag[1..])),
+            };
+            targets = rest;
+        }
+        _ => {}
+    }
+    if targets.is_empty() {
+        return error(
+            app,
+            String::from("kill: usage: kill [-s sig | -sig] %job | pid ..."),
+        );
+    }
+
+    let mut status = 0;
+    for target in targets.iter() {
+        if target.starts_with('%') {
+            let job = match app.find_job(target).and_then(|n| app.job_mut(n)) {
+                Some(job) => job,
+                None => {
+                    status = error(app, format!("kill: {}: no such job", target));
+                    continue;
+                }
+            };
+            signal_job(job, signal);
+            // A stopped job only sees the signal once it is continued.
+            if job.state == JobState::Stopped && signal != libc::SIGSTOP {
+                continue_job(job);
+            }
+        } else {
+            let result = match target.parse::<libc::pid_t>() {
+                Ok(pid) => unsafe { libc::kill(pid, signal) },
+                Err(_) => -1,
+            };
+            if result != 0 {
+                status = error(app, format!("kill: {}: no such process", target));
+            }
+        }
+    }
+    status
+}
+
+fn wait(args: &[String], app: &mut App) -> Builtin {
+    let mut jobs: Vec<usize> = Vec::new();
+    if args.is_empty() {
+        jobs = app
+            .jobs
+            .iter()
+            .filter(|job| job.state != JobState::Stopped)
+            .map(|job| job.number)
+            .collect();
+    }
+    for spec in args.iter() {
+        match app.find_job(spec) {
+            Some(number) => jobs.push(number),
+            None => return Builtin::Status(error(app, format!("wait: {}: no such job", spec))),
+        }
+    }
+    Builtin::Wait {
+        jobs,
+        last: !args.is_empty(),
+    }
+}
+
+/// The arguments of `export` and `local`, where the values of the
+/// `NAME=value` words are not split into fields.
+fn declaration_args(command: &SimpleCommand, app: &App) -> Vec<String> {
+    command.words[1..]
+        .iter()
+        .flat_map(|word| match Assignment::from_word(word) {
+            Some(assignment) => {
+                let value = expand_word(&assignment.value, app);
+                vec![format!("{}={}", assignment.name, value)]
+            }
+            None => expand_fields(word, app),
+        })
+        .collect()
+}
+
+/// Whether `words` name a builtin that can run as a pipeline stage. `c` and
+/// `help` are only known as whole command lines, and `env` with arguments
+/// is the external command.
+pub fn is_builtin(words: &[String]) -> bool {
+    match words.first().map(String::as_str) {
+        Some("c") | Some("help") | None => false,
+        Some("env") => words.len() == 1,
+        Some(name) => BUILTINS.contains(&name),
+    }
+}
+
+/// Run `pipeline` inside the shell if it is a single builtin command
+/// without redirections. Returns `None` when the pipeline has to be
+/// spawned, which also runs builtins with redirections, in a pipe or in
+/// the background.
+pub fn run_builtin(pipeline: &Pipeline, app: &mut App) -> Option<Builtin> {
+    if pipeline.commands.len() != 1 || pipeline.background {
+        return None;
+    }
+    let command = &pipeline.commands[0];
+    let words = expand_command(command, app);
+
+    // Assignments alone set shell variables. Redirections, if any, are
+    // still opened by spawning the (empty) pipeline.
+    if words.is_empty() {
+        for assignment in command.assignments.iter() {
+            let value = expand_word(&assignment.value, app);
+            app.set_var(&assignment.name, value);
+        }
+        return match command.redirects.is_empty() {
+            true => Some(Builtin::Status(0)),
+            false => None,
+        };
+    }
+    if !command.redirects.is_empty() || !is_builtin(&words) {
+        return None;
+    }
+
+    // Assignments before the name, as in `HOME=/tmp cd`, only last while
+    // the builtin runs.
+    let mut saved = Vec::new();
+    for assignment in command.assignments.iter() {
+        let value = expand_word(&assignment.value, app);
+        saved.push((
+            assignment.name.clone(),
+            app.vars.get(&assignment.name).cloned(),
+        ));
+        app.set_var(&assignment.name, value);
+    }
+    let builtin = builtin(&words, command, app);
+    for (name, var) in saved.into_iter().rev() {
+        match var {
+            Some(var) => app.vars.insert(name, var),
+            None => app.vars.remove(&name),
+        };
+    }
+    Some(builtin)
+}
+
+/// Run the builtin named by `words[0]`.
+fn builtin(words: &[String], command: &SimpleCommand, app: &mut App) -> Builtin {
+    let args = &words[1..];
+    let status = match words[0].as_str() {
+        "cd" => cd(args, app),
+        "pushd" => pushd(args, app),
+        "popd" => popd(args, app),
+        "dirs" => dirs(args, app),
+        "history" => history(args, app),
+        "set" => set(args, app),
+        "export" => export(&declaration_args(command, app), app),
+        "local" => local(&declaration_args(command, app), app),
+        "return" => return return_builtin(args, app),
+        "break" | "continue" => return loop_builtin(&words[0], args, app),
+        "shift" => shift(args, app),
+        "exit" => return exit(args, app),
+        "unset" => unset(args, app),
+        "alias" => alias(args, app),
+        "unalias" => unalias(args, app),
+        "env" => env(app),
+        "jobs" => jobs(args, app),
+        "fg" => return fg(args, app),
+        "bg" => bg(args, app),
+        "kill" => kill(args, app),
+        "wait" => return wait(args, app),
+        _ => unreachable!("{} is not a builtin", words[0]),
+    };
+    Builtin::Status(status)
+}
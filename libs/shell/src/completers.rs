@@ -0,0 +1,811 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use lib_app::*;
+
+use crate::completion::*;
+use crate::expand::{home_dir, pattern_matches};
+
+/// Register the built-in completers in `registry`.
+pub fn register(registry: &mut Registry) {
+    registry.register("git", Git);
+    registry.register("cargo", Cargo);
+    registry.register("make", Make);
+    registry.register("ssh", Ssh { scp: false });
+    registry.register("scp", Ssh { scp: true });
+}
+
+/// How long a command run for completions may take before it is killed.
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long the output of a command or the text of a file is reused.
+const CACHE_TIME: Duration = Duration::from_secs(10);
+
+/// The text kept under `key` if it is recent enough, or else the one `read`
+/// gives, which is kept for `CACHE_TIME`.
+fn cached(app: &mut App, key: String, read: impl FnOnce(&App) -> Option<String>) -> Option<String> {
+    if let Some((time, text)) = app.completion_sources.get(&key) {
+        if time.elapsed() < CACHE_TIME {
+            return text.clone();
+        }
+    }
+    let text = read(app);
+    app.completion_sources
+        .retain(|_, (time, _)| time.elapsed() < CACHE_TIME);
+    app.completion_sources
+        .insert(key, (Instant::now(), text.clone()));
+    text
+}
+
+/// The text of the file at `path`, if it can be read.
+fn read_file(app: &mut App, path: &Path) -> Option<String> {
+    cached(app, path.display().to_string(), |_| {
+        std::fs::read_to_string(path).ok()
+    })
+}
+
+/// The output of `program` run in the current directory, or none if it
+/// fails or takes longer than `COMMAND_TIMEOUT`, in which case it is killed
+/// along with the processes it started.
+fn run_program(app: &App, program: &str, args: &[&str]) -> Option<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(&app.path)
+        .env_clear()
+        .envs(app.exported_vars())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .ok()?;
+    // Read from a thread, so that a full pipe cannot stop the program.
+    let mut stdout = child.stdout.take()?;
+    let reader = std::thread::spawn(move || {
+        let mut text = Vec::new();
+        let _ = stdout.read_to_end(&mut text);
+        text
+    });
+
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(5)),
+            Ok(status) => break status,
+            Err(_) => break None,
+        }
+    };
+    if status.is_none() {
+        unsafe {
+            libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+        }
+        let _ = child.wait();
+    }
+    let text = reader.join().ok()?;
+    match status {
+        Some(status) if status.success() => Some(String::from_utf8_lossy(&text).to_string()),
+        _ => None,
+    }
+}
+
+/// The lines printed by `program` run in the current directory, or none if
+/// it fails. The output is reused for a while in the same directory.
+fn command_lines(app: &mut App, program: &str, args: &[&str]) -> Vec<String> {
+    let key = format!("{}: {} {}", app.path, program, args.join(" "));
+    let text = cached(app, key, |app| run_program(app, program, args));
+    text.unwrap_or_default()
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// The first argument that is not an option.
+fn subcommand(args: &[String]) -> Option<&str> {
+    args.iter()
+        .map(|arg| arg.as_str())
+        .find(|arg| !arg.starts_with('-'))
+}
+
+fn described(words: &[(&str, &str)]) -> Vec<Candidate> {
+    words
+        .iter()
+        .map(|(word, description)| Candidate::described(word, description))
+        .collect()
+}
+
+const GIT_SUBCOMMANDS: [(&str, &str); 30] = [
+    ("add", "Add file contents to the index"),
+    ("bisect", "Find the commit that introduced a bug"),
+    ("blame", "Show what revision last modified each line"),
+    ("branch", "List, create, or delete branches"),
+    ("checkout", "Switch branches or restore files"),
+    ("cherry-pick", "Apply the changes of existing commits"),
+    ("clean", "Remove untracked files"),
+    ("clone", "Clone a repository into a new directory"),
+    ("commit", "Record changes to the repository"),
+    ("config", "Get and set options"),
+    ("diff", "Show changes between commits and the working tree"),
+    ("fetch", "Download objects and refs from another repository"),
+    ("grep", "Print lines matching a pattern"),
+    ("help", "Display help information about Git"),
+    ("init", "Create an empty Git repository"),
+    ("log", "Show commit logs"),
+    ("merge", "Join two or more development histories"),
+    ("mv", "Move or rename a file"),
+    ("pull", "Fetch from and integrate with another repository"),
+    ("push", "Update remote refs"),
+    ("rebase", "Reapply commits on top of another base"),
+    ("remote", "Manage the tracked repositories"),
+    ("reset", "Reset the current HEAD to a state"),
+    ("restore", "Restore working tree files"),
+    ("revert", "Revert existing commits"),
+    ("rm", "Remove files from the working tree and the index"),
+    ("show", "Show objects"),
+    ("stash", "Stash the changes of the working directory"),
+    ("status", "Show the working tree status"),
+    ("switch", "Switch branches"),
+];
+
+const GIT_REMOTE_SUBCOMMANDS: [(&str, &str); 7] = [
+    ("add", "Add a remote"),
+    ("get-url", "Show the URL of a remote"),
+    ("prune", "Delete stale remote-tracking branches"),
+    ("remove", "Remove a remote"),
+    ("rename", "Rename a remote"),
+    ("set-url", "Change the URL of a remote"),
+    ("show", "Show information about a remote"),
+];
+
+/// Completes git subcommands and aliases, branches, tags and remotes.
+struct Git;
+
+impl Git {
+    /// The branches, then the remote-tracking branches, then the tags.
+    fn refs(app: &mut App) -> Vec<Candidate> {
+        let lines = command_lines(
+            app,
+            "git",
+            &[
+                "for-each-ref",
+                "--format=%(refname)",
+                "refs/heads",
+                "refs/remotes",
+                "refs/tags",
+            ],
+        );
+        let mut refs: Vec<Candidate> = Vec::new();
+        for kind in [
+            ("refs/heads/", "branch"),
+            ("refs/remotes/", "remote branch"),
+            ("refs/tags/", "tag"),
+        ] {
+            for line in lines.iter() {
+                match line.strip_prefix(kind.0) {
+                    Some(name) if !name.ends_with("/HEAD") => {
+                        refs.push(Candidate::described(name, kind.1))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        refs
+    }
+
+    fn remotes(app: &mut App) -> Vec<Candidate> {
+        command_lines(app, "git", &["remote"])
+            .iter()
+            .map(|remote| Candidate::described(remote, "remote"))
+            .collect()
+    }
+}
+
+impl Completer for Git {
+    fn complete(&self, app: &mut App, args: &[String], current: &str) -> Completions {
+        if current.starts_with('-') {
+            return Completions::Paths;
+        }
+        let subcommand = match subcommand(args) {
+            Some(subcommand) => subcommand,
+            None => {
+                let mut words = described(&GIT_SUBCOMMANDS);
+                for line in command_lines(app, "git", &["config", "--get-regexp", r"^alias\."]) {
+                    let (name, value) = line.split_once(' ').unwrap_or((&line, ""));
+                    words.push(Candidate::described(&name["alias.".len()..], value));
+                }
+                return Completions::Words(words);
+            }
+        };
+        // The arguments after the subcommand.
+        let rest: Vec<&String> = args
+            .iter()
+            .skip_while(|arg| arg.as_str() != subcommand)
+            .skip(1)
+            .filter(|arg| !arg.starts_with('-'))
+            .collect();
+
+        if args.iter().any(|arg| arg == "--") {
+            return Completions::Paths;
+        }
+        match subcommand {
+            "help" => Completions::Words(described(&GIT_SUBCOMMANDS)),
+            "branch" | "checkout" | "cherry-pick" | "diff" | "log" | "merge" | "rebase"
+            | "reset" | "revert" | "show" | "switch" => Completions::Words(Git::refs(app)),
+            "fetch" | "pull" | "push" => match rest.is_empty() {
+                true => Completions::Words(Git::remotes(app)),
+                false => Completions::Words(Git::refs(app)),
+            },
+            "remote" => match rest.first().map(|arg| arg.as_str()) {
+                None => Completions::Words(described(&GIT_REMOTE_SUBCOMMANDS)),
+                Some("get-url" | "prune" | "remove" | "rename" | "set-url" | "show")
+                    if rest.len() == 1 =>
+                {
+                    Completions::Words(Git::remotes(app))
+                }
+                Some(_) => Completions::Words(Vec::new()),
+            },
+            _ => Completions::Paths,
+        }
+    }
+}
+
+const CARGO_SUBCOMMANDS: [(&str, &str); 21] = [
+    ("add", "Add dependencies to a manifest file"),
+    ("bench", "Run the benchmarks"),
+    ("build", "Compile the current package"),
+    ("check", "Analyze the current package and report errors"),
+    ("clean", "Remove the target directory"),
+    ("doc", "Build the documentation"),
+    ("fetch", "Fetch the dependencies from the network"),
+    ("fix", "Fix the lint warnings reported by rustc"),
+    ("help", "Display help for a subcommand"),
+    ("init", "Create a new package in an existing directory"),
+    ("install", "Install a Rust binary"),
+    ("metadata", "Output the resolved dependencies of a package"),
+    ("new", "Create a new package"),
+    ("publish", "Upload a package to the registry"),
+    ("remove", "Remove dependencies from a manifest file"),
+    ("run", "Run a binary or example of the local package"),
+    ("search", "Search packages in the registry"),
+    ("test", "Run the tests"),
+    (
+        "tree",
+        "Display a tree visualization of the dependency graph",
+    ),
+    ("uninstall", "Remove a Rust binary"),
+    ("update", "Update the dependencies in the lock file"),
+];
+
+const CARGO_OPTIONS: [&str; 22] = [
+    "--all-features",
+    "--all-targets",
+    "--bench",
+    "--benches",
+    "--bin",
+    "--bins",
+    "--example",
+    "--examples",
+    "--features",
+    "--help",
+    "--lib",
+    "--manifest-path",
+    "--no-default-features",
+    "--offline",
+    "--package",
+    "--quiet",
+    "--release",
+    "--target",
+    "--target-dir",
+    "--test",
+    "--tests",
+    "--verbose",
+];
+
+/// The manifest of the package being worked on and its directory.
+struct Manifest {
+    dir: PathBuf,
+    table: toml::Table,
+}
+
+impl Manifest {
+    /// The closest `Cargo.toml` in the current directory or its parents.
+    fn find(app: &mut App) -> Option<Manifest> {
+        let start = PathBuf::from(&app.path);
+        let mut dir = Some(start.as_path());
+        while let Some(current) = dir {
+            let path = current.join("Cargo.toml");
+            if let Some(text) = read_file(app, &path) {
+                let table = text.parse::<toml::Table>().ok()?;
+                return Some(Manifest {
+                    dir: current.to_path_buf(),
+                    table,
+                });
+            }
+            dir = current.parent();
+        }
+        None
+    }
+
+    fn package_name(&self) -> Option<&str> {
+        self.table.get("package")?.get("name")?.as_str()
+    }
+
+    /// The targets of `kind` (`bin`, `example`, `test` or `bench`): those
+    /// declared in the manifest and those found in `dir`, either as a file or
+    /// as a directory with a `main.rs`.
+    fn targets(&self, kind: &str, dir: &str) -> Vec<Candidate> {
+        let mut names: Vec<String> = Vec::new();
+        if kind == "bin" && self.dir.join("src/main.rs").is_file() {
+            names.extend(self.package_name().map(|name| name.to_string()));
+        }
+        if let Some(targets) = self.table.get(kind).and_then(|t| t.as_array()) {
+            names.extend(
+                targets
+                    .iter()
+                    .filter_map(|target| target.get("name")?.as_str())
+                    .map(|name| name.to_string()),
+            );
+        }
+        if let Ok(entries) = std::fs::read_dir(self.dir.join(dir)) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let found = match path.extension() {
+                    Some(ext) => ext == "rs" && path.is_file(),
+                    None => path.join("main.rs").is_file(),
+                };
+                if found {
+                    if let Some(stem) = path.file_stem() {
+                        names.push(stem.to_string_lossy().to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+            .iter()
+            .map(|name| Candidate::described(name, kind))
+            .collect()
+    }
+
+    fn features(&self) -> Vec<Candidate> {
+        let features = match self.table.get("features").and_then(|f| f.as_table()) {
+            Some(features) => features,
+            None => return Vec::new(),
+        };
+        features
+            .iter()
+            .map(|(name, enables)| {
+                let enables: Vec<&str> = enables
+                    .as_array()
+                    .map(|list| list.iter().filter_map(|f| f.as_str()).collect())
+                    .unwrap_or_default();
+                Candidate::described(name, &enables.join(", "))
+            })
+            .collect()
+    }
+
+    /// The package itself and its dependencies, for `--package`.
+    fn packages(&self) -> Vec<Candidate> {
+        let mut packages: Vec<Candidate> = Vec::new();
+        if let Some(name) = self.package_name() {
+            packages.push(Candidate::described(name, "package"));
+        }
+        for (section, kind) in [
+            ("dependencies", "dependency"),
+            ("dev-dependencies", "dev-dependency"),
+            ("build-dependencies", "build-dependency"),
+        ] {
+            if let Some(deps) = self.table.get(section).and_then(|d| d.as_table()) {
+                packages.extend(deps.keys().map(|dep| Candidate::described(dep, kind)));
+            }
+        }
+        packages
+    }
+}
+
+/// Completes cargo subcommands, including the installed `cargo-*` ones,
+/// options, and the targets, features and packages of the manifest.
+struct Cargo;
+
+impl Completer for Cargo {
+    fn complete(&self, app: &mut App, args: &[String], current: &str) -> Completions {
+        let mut manifest = || Manifest::find(app);
+        match args.last().map(|arg| arg.as_str()) {
+            Some("--bin") => {
+                return Completions::Words(
+                    manifest().map_or(Vec::new(), |m| m.targets("bin", "src/bin")),
+                )
+            }
+            Some("--example") => {
+                return Completions::Words(
+                    manifest().map_or(Vec::new(), |m| m.targets("example", "examples")),
+                )
+            }
+            Some("--test") => {
+                return Completions::Words(
+                    manifest().map_or(Vec::new(), |m| m.targets("test", "tests")),
+                )
+            }
+            Some("--bench") => {
+                return Completions::Words(
+                    manifest().map_or(Vec::new(), |m| m.targets("bench", "benches")),
+                )
+            }
+            Some("--features" | "-F") => {
+                return Completions::Words(manifest().map_or(Vec::new(), |m| m.features()))
+            }
+            Some("--package" | "-p") => {
+                return Completions::Words(manifest().map_or(Vec::new(), |m| m.packages()))
+            }
+            Some("--manifest-path") => return Completions::Paths,
+            Some("--target-dir") => return Completions::Directories,
+            _ => {}
+        }
+        if args.iter().any(|arg| arg == "--") {
+            return Completions::Paths;
+        }
+        if current.starts_with('-') {
+            return Completions::Words(
+                CARGO_OPTIONS
+                    .iter()
+                    .map(|option| Candidate::new(option))
+                    .collect(),
+            );
+        }
+
+        match subcommand(args) {
+            None | Some("help") => {
+                let mut words = described(&CARGO_SUBCOMMANDS);
+                for executable in app.path_executables() {
+                    if let Some(name) = executable.strip_prefix("cargo-") {
+                        words.push(Candidate::described(name, executable));
+                    }
+                }
+                Completions::Words(words)
+            }
+            Some("new" | "init") => Completions::Directories,
+            Some("install") => Completions::Paths,
+            Some(_) => Completions::Words(Vec::new()),
+        }
+    }
+}
+
+/// Completes the targets of the makefile, described by the `##` comment
+/// that follows them, if any.
+struct Make;
+
+impl Make {
+    /// The makefile `make` would read: the one given with `-f`, or the first
+    /// of `GNUmakefile`, `makefile` and `Makefile`, in the `-C` directory.
+    fn makefile(app: &App, args: &[String]) -> Option<PathBuf> {
+        let mut dir = PathBuf::from(&app.path);
+        let mut file: Option<String> = None;
+        for pair in args.windows(2) {
+            match pair[0].as_str() {
+                "-C" | "--directory" => dir = dir.join(&pair[1]),
+                "-f" | "--file" | "--makefile" => file = Some(pair[1].clone()),
+                _ => {}
+            }
+        }
+        match file {
+            Some(file) => Some(dir.join(file)),
+            None => ["GNUmakefile", "makefile", "Makefile"]
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|path| path.is_file()),
+        }
+    }
+
+    fn targets(text: &str) -> Vec<Candidate> {
+        let mut targets: Vec<Candidate> = Vec::new();
+        for line in text.lines() {
+            if line.starts_with(['\t', '#', ' ']) {
+                continue;
+            }
+            let (rule, comment) = line.split_once("##").unwrap_or((line, ""));
+            let colon = match rule.find(':') {
+                Some(colon) => colon,
+                None => continue,
+            };
+            // `NAME := value` and `NAME = value` are variables.
+            if rule[..colon].contains('=')
+                || rule[colon..].starts_with(":=")
+                || rule[colon..].starts_with("::=")
+            {
+                continue;
+            }
+            for target in rule[..colon].split_whitespace() {
+                if target.starts_with('.') || target.contains(['%', '$']) {
+                    continue;
+                }
+                targets.push(Candidate::described(target, comment.trim()));
+            }
+        }
+        targets
+    }
+}
+
+impl Completer for Make {
+    fn complete(&self, app: &mut App, args: &[String], current: &str) -> Completions {
+        match args.last().map(|arg| arg.as_str()) {
+            Some("-f" | "--file" | "--makefile") => return Completions::Paths,
+            Some("-C" | "--directory") => return Completions::Directories,
+            _ => {}
+        }
+        if current.starts_with('-') {
+            return Completions::Paths;
+        }
+        let text = Make::makefile(app, args).and_then(|path| read_file(app, &path));
+        match text {
+            Some(text) => Completions::Words(Make::targets(&text)),
+            None => Completions::Words(Vec::new()),
+        }
+    }
+}
+
+/// Options of `ssh` and `scp` followed by a file.
+const SSH_FILE_OPTIONS: [&str; 4] = ["-F", "-i", "-E", "-S"];
+/// Other options followed by a value.
+const SSH_VALUE_OPTIONS: [&str; 16] = [
+    "-B", "-b", "-c", "-D", "-e", "-I", "-J", "-L", "-l", "-m", "-O", "-o", "-P", "-p", "-R", "-W",
+];
+
+/// Completes the hosts of `~/.ssh/config`, prefixed with the user if one is
+/// typed. For `scp`, hosts are followed by a `:` and arguments with a `/`
+/// or `:` complete local paths.
+struct Ssh {
+    scp: bool,
+}
+
+impl Ssh {
+    /// The hosts named by the `Host` lines of `path` and of the files it
+    /// includes, described by their `HostName`. Patterns are skipped.
+    /// `including` are the files whose includes led to `path`, so that a
+    /// file including itself, directly or not, is only read once.
+    fn hosts(app: &mut App, path: &Path, hosts: &mut Vec<Candidate>, including: &mut Vec<PathBuf>) {
+        if including.iter().any(|file| file == path) {
+            return;
+        }
+        let text = match read_file(app, path) {
+            Some(text) => text,
+            None => return,
+        };
+        let dir = path.parent().unwrap_or(Path::new("/"));
+        let mut block: Vec<usize> = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            let (keyword, value) = match line.split_once([' ', '\t', '=']) {
+                Some((keyword, value)) => (
+                    keyword.to_lowercase(),
+                    value.trim_start_matches([' ', '\t', '=']).trim(),
+                ),
+                None => continue,
+            };
+            match keyword.as_str() {
+                "host" => {
+                    block.clear();
+                    for host in value.split_whitespace() {
+                        if !host.contains(['*', '?', '!']) {
+                            block.push(hosts.len());
+                            hosts.push(Candidate::new(host));
+                        }
+                    }
+                }
+                "hostname" => {
+                    for i in block.iter() {
+                        hosts[*i].description = value.to_string();
+                    }
+                }
+                "include" => {
+                    for file in value.split_whitespace() {
+                        including.push(path.to_path_buf());
+                        for file in Ssh::include_paths(app, dir, file) {
+                            Ssh::hosts(app, &file, hosts, including);
+                        }
+                        including.pop();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The files an `Include` of `file` reads: `~` is the home directory,
+    /// relative paths are in `dir`, and wildcards in the file name match
+    /// the files of its directory in order. Wildcards in directories are
+    /// not expanded, so those includes are skipped.
+    fn include_paths(app: &App, dir: &Path, file: &str) -> Vec<PathBuf> {
+        let path = match file.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => match home_dir("", app) {
+                Some(home) => PathBuf::from(format!("{}{}", home, rest)),
+                None => return Vec::new(),
+            },
+            _ => dir.join(file),
+        };
+        let (parent, name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (parent, name.to_string_lossy()),
+            _ => return vec![path],
+        };
+        if parent.to_string_lossy().contains(['*', '?', '[']) {
+            return Vec::new();
+        }
+        if !name.contains(['*', '?', '[']) {
+            return vec![path];
+        }
+        let entries = match std::fs::read_dir(parent) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .filter(|entry| {
+                let file = entry.file_name().to_string_lossy().into_owned();
+                // As with globs, only a pattern starting with `.` matches
+                // hidden files.
+                (name.starts_with('.') || !file.starts_with('.')) && pattern_matches(&name, &file)
+            })
+            .map(|entry| entry.path())
+            .collect();
+        paths.sort();
+        paths
+    }
+}
+
+impl Completer for Ssh {
+    fn complete(&self, app: &mut App, args: &[String], current: &str) -> Completions {
+        match args.last().map(|arg| arg.as_str()) {
+            Some(option) if SSH_FILE_OPTIONS.contains(&option) => return Completions::Paths,
+            Some("-J") => {}
+            Some(option) if SSH_VALUE_OPTIONS.contains(&option) => {
+                return Completions::Words(Vec::new())
+            }
+            _ => {}
+        }
+        if current.starts_with('-') {
+            return Completions::Words(Vec::new());
+        }
+        if self.scp && (current.contains(['/', ':']) || current.starts_with(['.', '~'])) {
+            return Completions::Paths;
+        }
+        // Arguments after the host are a remote command for ssh.
+        if !self.scp {
+            let mut after_value = false;
+            for arg in args {
+                match after_value || arg.starts_with('-') {
+                    true => {
+                        after_value = SSH_FILE_OPTIONS.contains(&arg.as_str())
+                            || SSH_VALUE_OPTIONS.contains(&arg.as_str())
+                    }
+                    false => return Completions::Paths,
+                }
+            }
+        }
+
+        let mut hosts: Vec<Candidate> = Vec::new();
+        if let Some(home) = app.var("HOME") {
+            let path = Path::new(home).join(".ssh/config");
+            Ssh::hosts(app, &path, &mut hosts, &mut Vec::new());
+        }
+        let user = current
+            .split_once('@')
+            .map_or(String::new(), |(user, _)| format!("{}@", user));
+        let suffix = if self.scp { ":" } else { "" };
+        Completions::Words(
+            hosts
+                .iter()
+                .map(|host| Candidate {
+                    word: format!("{}{}{}", user, host.word, suffix),
+                    description: host.description.clone(),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// What a spec completes when none of its words apply.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Fallback {
+    #[default]
+    Paths,
+    Directories,
+    None,
+}
+
+/// A completer declared in `completions.toml`, under the name of its
+/// command:
+///
+/// ```toml
+/// [docker]
+/// subcommands = ["build", "run", "ps"]
+/// options = ["--help", "--version"]
+/// descriptions = { build = "Build an image", ps = "List containers" }
+/// fallback = "paths"
+///
+/// [docker.arguments]
+/// run = ["--rm", "--detach"]
+///
+/// [docker.commands]
+/// rm = "docker ps --format '{{.Names}}'"
+/// ```
+///
+/// `subcommands` are offered for the first argument. The later ones are
+/// completed from the `arguments` and the output lines of the `commands`
+/// of their subcommand, or of `*` for any subcommand. Arguments starting
+/// with `-` are completed from `options`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Spec {
+    subcommands: Vec<String>,
+    options: Vec<String>,
+    descriptions: HashMap<String, String>,
+    arguments: HashMap<String, Vec<String>>,
+    commands: HashMap<String, String>,
+    fallback: Fallback,
+}
+
+impl Spec {
+    fn candidates<'a>(&self, words: impl IntoIterator<Item = &'a String>) -> Vec<Candidate> {
+        words
+            .into_iter()
+            .map(|word| match self.descriptions.get(word) {
+                Some(description) => Candidate::described(word, description),
+                None => Candidate::new(word),
+            })
+            .collect()
+    }
+}
+
+impl Completer for Spec {
+    fn complete(&self, app: &mut App, args: &[String], current: &str) -> Completions {
+        if current.starts_with('-') && !self.options.is_empty() {
+            return Completions::Words(self.candidates(&self.options));
+        }
+        let subcommand = match subcommand(args) {
+            None if !self.subcommands.is_empty() => {
+                return Completions::Words(self.candidates(&self.subcommands))
+            }
+            _ if self.subcommands.is_empty() => "*",
+            subcommand => subcommand.unwrap_or("*"),
+        };
+
+        let mut words: Vec<String> = Vec::new();
+        for key in [subcommand, "*"] {
+            words.extend(self.arguments.get(key).into_iter().flatten().cloned());
+            if let Some(command) = self.commands.get(key) {
+                words.extend(command_lines(app, "sh", &["-c", command]));
+            }
+            if subcommand == "*" {
+                break;
+            }
+        }
+        match (words.is_empty(), self.fallback) {
+            (false, _) | (true, Fallback::None) => Completions::Words(self.candidates(&words)),
+            (true, Fallback::Paths) => Completions::Paths,
+            (true, Fallback::Directories) => Completions::Directories,
+        }
+    }
+}
+
+/// Register the completers declared in the spec file, which replace the
+/// built-in ones of the same command.
+pub fn load_specs(registry: &mut Registry, app: &App) -> Result<(), String> {
+    let path = match app.config_dir() {
+        Some(dir) => dir.join("completions.toml"),
+        None => return Ok(()),
+    };
+    let specs: HashMap<String, Spec> = match read_toml(&path)? {
+        Some(specs) => specs,
+        None => return Ok(()),
+    };
+    for (name, spec) in specs {
+        registry.register(&name, spec);
+    }
+    Ok(())
+}
@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::OnceLock;
+
+use lib_app::*;
+
+use crate::builtins::BUILTINS;
+use crate::completers;
+use crate::expand::*;
+use crate::parser::*;
+
+/// A word offered by a completer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub word: String,
+    /// A short description, empty when there is none.
+    pub description: String,
+}
+
+impl Candidate {
+    pub fn new(word: &str) -> Candidate {
+        Candidate {
+            word: word.to_string(),
+            description: String::new(),
+        }
+    }
+
+    pub fn described(word: &str, description: &str) -> Candidate {
+        Candidate {
+            word: word.to_string(),
+            description: description.to_string(),
+        }
+    }
+}
+
+/// What to offer for an argument.
+pub enum Completions {
+    /// File and directory paths.
+    Paths,
+    /// Directory paths only.
+    Directories,
+    /// These words, filtered by what was typed.
+    Words(Vec<Candidate>),
+}
+
+/// Completes the arguments of a command.
+pub trait Completer: Send + Sync {
+    /// What to offer for `current`, the argument being typed, given the
+    /// arguments before it. Both are expanded.
+    fn complete(&self, app: &mut App, args: &[String], current: &str) -> Completions;
+}
+
+/// The completers of the commands that have one, by command name.
+pub struct Registry {
+    completers: HashMap<String, Box<dyn Completer>>,
+}
+
+impl Registry {
+    /// A registry with the completers of the builtins and the built-in ones
+    /// for `git`, `cargo`, `make`, `ssh` and `scp`.
+    pub fn new() -> Registry {
+        let mut registry = Registry {
+            completers: HashMap::new(),
+        };
+        registry.register("cd", Cd);
+        registry.register("pushd", Pushd);
+        registry.register("popd", Popd);
+        completers::register(&mut registry);
+        registry
+    }
+
+    /// Use `completer` for the arguments of `name`, replacing the one it had.
+    pub fn register(&mut self, name: &str, completer: impl Completer + 'static) {
+        self.completers
+            .insert(name.to_string(), Box::new(completer));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Completer> {
+        self.completers
+            .get(name)
+            .map(|completer| completer.as_ref())
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::new()
+    }
+}
+
+/// The completers of the session, built once by `load_completions`.
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// Build the completers of the session, with those declared in
+/// `completions.toml`. When the file has an error, only the built-in ones
+/// are used.
+pub fn load_completions(app: &App) -> Result<(), String> {
+    let mut registry = Registry::new();
+    let result = completers::load_specs(&mut registry, app);
+    let _ = REGISTRY.set(registry);
+    result
+}
+
+fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::new)
+}
+
+/// The `+n` entries of the directory stack, described by their path.
+fn stack_entries(app: &App) -> Vec<Candidate> {
+    app.dirs()
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(n, dir)| Candidate::described(&format!("+{}", n), &app.tilde(dir)))
+        .collect()
+}
+
+struct Cd;
+
+impl Completer for Cd {
+    fn complete(&self, _app: &mut App, _args: &[String], _current: &str) -> Completions {
+        Completions::Directories
+    }
+}
+
+struct Pushd;
+
+impl Completer for Pushd {
+    fn complete(&self, app: &mut App, _args: &[String], current: &str) -> Completions {
+        match current.starts_with('+') {
+            true => Completions::Words(stack_entries(app)),
+            false => Completions::Directories,
+        }
+    }
+}
+
+struct Popd;
+
+impl Completer for Popd {
+    fn complete(&self, app: &mut App, _args: &[String], _current: &str) -> Completions {
+        Completions::Words(stack_entries(app))
+    }
+}
+
+fn push_completion(app: &mut App, line: String, display: String, description: &str) {
+    app.completion.push(line);
+    app.completion_display.push(display);
+    app.completion_descriptions.push(description.to_string());
+}
+
+/// Offer the aliases, functions, builtins and the executables on `$PATH`
+/// whose name starts with the command name being typed.
+fn command_completion(app: &mut App, word: Option<&Word>) {
+    let (prefix, name) = match word {
+        Some(word) => (app.input[..word.start].to_string(), word.text()),
+        None => (app.input.clone(), String::new()),
+    };
+
+    // Aliases and functions come first, so that they win over the commands
+    // they shadow.
+    let mut names: Vec<(String, String)> = app
+        .aliases
+        .iter()
+        .filter(|(alias, _)| alias.starts_with(&name))
+        .map(|(alias, value)| (alias.clone(), value.clone()))
+        .collect();
+    names.extend(
+        app.functions
+            .keys()
+            .filter(|function| function.starts_with(&name))
+            .map(|function| (function.clone(), String::from("function"))),
+    );
+    names.extend(
+        BUILTINS
+            .iter()
+            .filter(|builtin| builtin.starts_with(&name))
+            .map(|builtin| (builtin.to_string(), String::from("builtin"))),
+    );
+    let executables = app.path_executables();
+    names.extend(
+        executables
+            .iter()
+            .filter(|executable| executable.starts_with(&name))
+            .map(|executable| (executable.clone(), String::from("command"))),
+    );
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+    names.dedup_by(|a, b| a.0 == b.0);
+
+    for (name, description) in names {
+        push_completion(app, prefix.clone() + &name + " ", name, &description);
+    }
+}
+
+/// Offer the `candidates` starting with the argument being typed. Words
+/// ending with `/`, `:` or `=` are not followed by a space, so that the
+/// argument can be continued.
+fn word_completion(app: &mut App, word: Option<&Word>, candidates: Vec<Candidate>) {
+    let (start, value) = match word {
+        Some(word) => (word.start, expand_word(word, app)),
+        None => (app.input.len(), String::new()),
+    };
+    let quote = app.input[start..]
+        .chars()
+        .next()
+        .filter(|c| *c == '\'' || *c == '"');
+
+    let mut seen: Vec<String> = Vec::new();
+    for candidate in candidates {
+        if !candidate.word.starts_with(&value) || seen.contains(&candidate.word) {
+            continue;
+        }
+        let suffix = match candidate.word.ends_with(['/', ':', '=']) {
+            true => "",
+            false => " ",
+        };
+        let line = app.input[..start].to_string() + &quote_name(&candidate.word, quote) + suffix;
+        push_completion(app, line, candidate.word.clone(), &candidate.description);
+        seen.push(candidate.word);
+    }
+}
+
+/// The byte index of the last `/` of `source` that is neither quoted nor
+/// escaped.
+fn last_bare_slash(source: &str) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    let mut slash = None;
+    let mut chars = source.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        match (quote, c) {
+            (None, '\\') => {
+                chars.next();
+            }
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '/') => slash = Some(pos),
+            (Some(q), c) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    slash
+}
+
+/// `name` written so that the parser reads it back unchanged, inside
+/// `quote` if the name being completed was opened with one.
+fn quote_name(name: &str, quote: Option<char>) -> String {
+    match quote {
+        Some('\'') => format!("'{}'", name.replace('\'', "'\\''")),
+        Some(_) => {
+            let mut quoted = String::from("\"");
+            for c in name.chars() {
+                if matches!(c, '"' | '\\' | '$' | '`') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted.push('"');
+            quoted
+        }
+        None => {
+            let mut escaped = String::new();
+            for (i, c) in name.chars().enumerate() {
+                let special = c.is_whitespace() || "'\"\\$`|&;<>()*?[]{}!#".contains(c);
+                if special || (i == 0 && c == '~') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        }
+    }
+}
+
+/// `size` in bytes, in the largest unit that keeps it above one.
+fn human_size(size: u64) -> String {
+    let mut value = size as f64;
+    for unit in ["B", "K", "M", "G"] {
+        if value < 1024.0 {
+            return match unit {
+                "B" => format!("{}B", size),
+                _ if value < 10.0 => format!("{:.1}{}", value, unit),
+                _ => format!("{:.0}{}", value, unit),
+            };
+        }
+        value /= 1024.0;
+    }
+    format!("{:.0}T", value)
+}
+
+/// The type of a file and, for regular files, its size, such as
+/// `directory`, `1.2K` or `link, executable, 14M`.
+fn describe_file(metadata: &std::fs::Metadata, is_link: bool) -> String {
+    let mut kinds: Vec<String> = Vec::new();
+    if is_link {
+        kinds.push(String::from("link"));
+    }
+    if metadata.is_dir() {
+        kinds.push(String::from("directory"));
+    } else {
+        if metadata.permissions().mode() & 0o111 != 0 {
+            kinds.push(String::from("executable"));
+        }
+        kinds.push(human_size(metadata.len()));
+    }
+    kinds.join(", ")
+}
+
+/// Offer the files (or only the directories) whose name starts with the
+/// last path component of `word`. Hidden files are offered once that
+/// component starts with a dot, and symlinks to directories count as
+/// directories. The directory part of the word is kept as it was typed, so
+/// `~`, variables and quotes survive the completion.
+fn path_completion(app: &mut App, word: Option<&Word>, dirs_only: bool) {
+    let (start, value) = match word {
+        Some(word) => (word.start, expand_word(word, app)),
+        None => (app.input.len(), String::new()),
+    };
+    let source = app.input[start..].to_string();
+
+    let (dir, prefix) = match value.rfind('/') {
+        Some(slash) => value.split_at(slash + 1),
+        None => ("", value.as_str()),
+    };
+    // When the last slash is quoted, the whole path is written again.
+    let (kept, rest, dir_name) = match last_bare_slash(&source) {
+        Some(slash) if !source[slash + 1..].contains('/') => {
+            (&source[..=slash], &source[slash + 1..], "")
+        }
+        _ => ("", source.as_str(), dir),
+    };
+    let quote = rest.chars().next().filter(|c| *c == '\'' || *c == '"');
+
+    let search = match dir {
+        "" => app.path.clone(),
+        dir if dir.starts_with('/') => dir.to_string(),
+        dir => format!("{}/{}", app.path, dir),
+    };
+    let entries = match std::fs::read_dir(&search) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let mut names: Vec<(String, bool, String)> = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+        // Follows symlinks, so a link to a directory completes like one.
+        let metadata = match std::fs::metadata(entry.path()) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if dirs_only && !metadata.is_dir() {
+            continue;
+        }
+        let is_link = entry.file_type().is_ok_and(|kind| kind.is_symlink());
+        names.push((name, metadata.is_dir(), describe_file(&metadata, is_link)));
+    }
+    names.sort();
+
+    let line = app.input[..start].to_string() + kept;
+    for (name, is_dir, description) in names {
+        let (suffix, display) = match is_dir {
+            true => ("/", format!("{}/", name)),
+            false => (" ", name.clone()),
+        };
+        let path = quote_name(&(dir_name.to_string() + &name), quote);
+        push_completion(app, line.clone() + &path + suffix, display, &description);
+    }
+}
+
+/// Find the completions of the word before the cursor, as when entering
+/// completion mode.
+pub fn start_completion(app: &mut App) {
+    app.completion_base = None;
+    update_completion(app);
+}
+
+/// Replace the completions with those of the word before the cursor,
+/// selecting the first one. While more of the same word is typed, the
+/// candidates found for its start are only filtered.
+pub fn update_completion(app: &mut App) {
+    // The text before the cursor is completed, and the rest of the line
+    // follows each completion.
+    let rest = app.input.split_off(app.editor.cursor);
+
+    // A new word, directory or quote, or a hidden file, needs new
+    // candidates.
+    let base = app.completion_base.as_deref().unwrap_or_default();
+    let refresh = match app.input.strip_prefix(base) {
+        Some(typed) if app.completion_base.is_some() => {
+            typed.starts_with('.')
+                || typed.contains(|c: char| c.is_whitespace() || "/'\"\\$~".contains(c))
+        }
+        _ => true,
+    };
+    if refresh {
+        app.completion.clear();
+        app.completion_display.clear();
+        app.completion_descriptions.clear();
+        create_completion(app);
+        app.completion_base = Some(app.input.clone());
+        app.completion_cache = app
+            .completion
+            .drain(..)
+            .zip(app.completion_display.drain(..))
+            .zip(app.completion_descriptions.drain(..))
+            .map(|((line, display), description)| (line, display, description))
+            .collect();
+    }
+
+    app.completion.clear();
+    app.completion_display.clear();
+    app.completion_descriptions.clear();
+    app.completion_index = 0;
+    for (line, display, description) in app.completion_cache.iter() {
+        if line.starts_with(&app.input) {
+            app.completion.push(line.clone() + &rest);
+            app.completion_display.push(display.clone());
+            app.completion_descriptions.push(description.clone());
+        }
+    }
+    app.input.push_str(&rest);
+}
+
+/// Where the simple command at the end of `input` starts, or `None` when
+/// a command name is expected: at the start of the line, after an
+/// operator or after a keyword such as `then`.
+fn command_start(input: &str) -> Option<usize> {
+    let tokens = match tokenize(input) {
+        Err(ParseError::UnterminatedQuote(quote)) => tokenize(&format!("{}{}", input, quote)),
+        tokens => tokens,
+    };
+    let mut start = None;
+    let mut command_position = true;
+    let mut tokens = tokens.unwrap_or_default().into_iter();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            // A keyword still being typed may be the start of a name.
+            Token::Word(word)
+                if command_position && word.end < input.len() && is_command_keyword(&word) =>
+            {
+                start = None
+            }
+            Token::Word(word) if command_position => {
+                start.get_or_insert(word.start);
+                command_position = Assignment::from_word(&word).is_some();
+            }
+            Token::Word(_) => {}
+            Token::Redirect(..) => {
+                tokens.next();
+            }
+            _ => {
+                start = None;
+                command_position = true;
+            }
+        }
+    }
+    start
+}
+
+/// Fill `app.completion` with the candidates for the word at the end of
+/// the input line.
+pub fn create_completion(app: &mut App) {
+    let start = match command_start(&app.input) {
+        Some(start) => start,
+        None => {
+            command_completion(app, None);
+            return;
+        }
+    };
+    // The commands before are blanked out, as they may be incomplete, as
+    // in `if test -f x; then ca`. The offsets of the words stay the same.
+    let input = format!("{}{}", " ".repeat(start), &app.input[start..]);
+
+    let list = match parse(&input) {
+        Ok(list) => list,
+        // Complete inside an open quote as if it was closed.
+        Err(ParseError::UnterminatedQuote(quote)) => match parse(&format!("{}{}", input, quote)) {
+            Ok(list) => list,
+            Err(_) => return,
+        },
+        // A keyword being typed, such as `if`, may start a command name.
+        Err(_) => {
+            if let Ok([Token::Word(word)]) = tokenize(&input).as_deref() {
+                command_completion(app, Some(word));
+            }
+            return;
+        }
+    };
+    let command = match list.items.last().map(|item| &item.first) {
+        Some(Command::Pipeline(pipeline)) => match pipeline.commands.last() {
+            Some(command) => command.clone(),
+            None => return,
+        },
+        _ => return,
+    };
+
+    // The word under the cursor, or none if a new word is being started.
+    let end = app.input.len();
+    let current = command
+        .words
+        .iter()
+        .chain(command.redirects.iter().map(|r| &r.target))
+        .chain(command.assignments.iter().map(|a| &a.value))
+        .find(|word| word.end >= end);
+    let is_name = match (current, command.words.first()) {
+        (None, None) => true,
+        (Some(word), Some(name)) => word == name,
+        _ => false,
+    };
+
+    if is_name {
+        match current.filter(|word| word.text().contains('/')) {
+            Some(path) => path_completion(app, Some(path), false),
+            None => command_completion(app, current),
+        }
+        return;
+    }
+
+    // Redirection targets and assignment values are paths.
+    let current = match current {
+        Some(word) if !command.words.contains(word) => {
+            path_completion(app, Some(word), false);
+            return;
+        }
+        current => current,
+    };
+
+    let name = expand_word(&command.words[0], app);
+    let args: Vec<String> = command.words[1..]
+        .iter()
+        .filter(|word| Some(*word) != current)
+        .map(|word| expand_word(word, app))
+        .collect();
+    let value = current.map_or(String::new(), |word| expand_word(word, app));
+
+    // An alias completes as the command it stands for, with the words of
+    // its value before the arguments.
+    let alias: Vec<String> = match app.aliases.get(&name).map(|alias| tokenize(alias)) {
+        Some(Ok(tokens)) => tokens
+            .iter()
+            .map_while(|token| match token {
+                Token::Word(word) => Some(expand_word(word, app)),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let (name, args) = match alias.split_first() {
+        Some((name, words)) => (name.clone(), [words, &args].concat()),
+        None => (name, args),
+    };
+
+    let completions = match registry().get(&name) {
+        Some(completer) => completer.complete(app, &args, &value),
+        None => Completions::Paths,
+    };
+    match completions {
+        Completions::Paths => path_completion(app, current, false),
+        Completions::Directories => path_completion(app, current, true),
+        Completions::Words(candidates) => word_completion(app, current, candidates),
+    }
+}
@@ -0,0 +1,170 @@
+use lib_app::*;
+
+use crate::builtins::BUILTINS;
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Why a command could not be started.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    NotFound(String),
+    PermissionDenied(String),
+    NotExecutable(String),
+    BadCwd(String),
+}
+
+impl CommandError {
+    /// Classify the error returned when spawning `cmd` failed.
+    pub fn from_spawn(cmd: &str, err: io::Error, cwd: &str) -> CommandError {
+        if !Path::new(cwd).is_dir() {
+            return CommandError::BadCwd(cwd.to_string());
+        }
+        // The file exists but is not a binary nor a script.
+        if err.raw_os_error() == Some(libc::ENOEXEC) || Path::new(cmd).is_dir() {
+            return CommandError::NotExecutable(cmd.to_string());
+        }
+        match err.kind() {
+            io::ErrorKind::NotFound => CommandError::NotFound(cmd.to_string()),
+            io::ErrorKind::PermissionDenied => CommandError::PermissionDenied(cmd.to_string()),
+            _ => CommandError::NotExecutable(cmd.to_string()),
+        }
+    }
+
+    /// The exit status reported for the command, as in other shells.
+    pub fn status(&self) -> i32 {
+        match self {
+            CommandError::NotFound(_) => 127,
+            CommandError::PermissionDenied(_) | CommandError::NotExecutable(_) => 126,
+            CommandError::BadCwd(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::NotFound(cmd) => write!(f, "{}: command not found", cmd),
+            CommandError::PermissionDenied(cmd) => write!(f, "{}: permission denied", cmd),
+            CommandError::NotExecutable(cmd) => write!(f, "{}: not an executable file", cmd),
+            CommandError::BadCwd(cwd) => {
+                write!(f, "current directory {} does not exist anymore", cwd)
+            }
+        }
+    }
+}
+
+/// Edit distance between `a` and `b`, counting a swap of two neighbouring
+/// characters as a single edit.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+/// The known command closest to `cmd`, if one is close enough to be a typo.
+pub fn suggest(cmd: &str, app: &mut App) -> Option<String> {
+    let mut candidates = app.path_executables().to_vec();
+    candidates.extend(BUILTINS.iter().map(|b| b.to_string()));
+    closest(cmd, candidates)
+}
+
+/// The candidate closest to `cmd`: one edit away for names shorter than six
+/// characters, two for longer ones.
+fn closest(cmd: &str, candidates: Vec<String>) -> Option<String> {
+    let max = (cmd.chars().count() / 3).clamp(1, 2);
+    candidates
+        .into_iter()
+        .map(|name| (distance(cmd, &name), name))
+        .filter(|(d, _)| *d <= max)
+        .min()
+        .map(|(_, name)| name)
+}
+
+/// Print `err` in the Outputs panel, with a suggestion for unknown commands.
+pub fn report_error(app: &mut App, err: &CommandError) {
+    app.output
+        .push_str(&format!("<c>{}</c>\n", escape_markup(&err.to_string())));
+    if let CommandError::NotFound(cmd) = err {
+        if cmd.contains('/') {
+            return;
+        }
+        if let Some(name) = suggest(cmd, app) {
+            app.output.push_str(&format!(
+                "<i>did you mean</i> <h2>{}</h2><i>?</i>\n",
+                escape_markup(&name)
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("ls", "ls"), 0);
+        assert_eq!(distance("", "abc"), 3);
+        assert_eq!(distance("abc", ""), 3);
+        assert_eq!(distance("gti", "git"), 1);
+        assert_eq!(distance("grpe", "grep"), 1);
+        assert_eq!(distance("car", "cat"), 1);
+        assert_eq!(distance("ct", "cat"), 1);
+        assert_eq!(distance("caat", "cat"), 1);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("été", "ete"), 2);
+    }
+
+    #[test]
+    fn suggestion_threshold() {
+        let known = names(&["git", "grep", "cargo", "python3"]);
+        assert_eq!(closest("gti", known.clone()), Some("git".to_string()));
+        assert_eq!(closest("grpe", known.clone()), Some("grep".to_string()));
+        // Short names allow a single edit.
+        assert_eq!(closest("gpr", known.clone()), None);
+        assert_eq!(closest("x", known.clone()), None);
+        // Six characters or more allow two.
+        assert_eq!(
+            closest("pyhon33", known.clone()),
+            Some("python3".to_string())
+        );
+        assert_eq!(closest("cragoo", known.clone()), Some("cargo".to_string()));
+        assert_eq!(closest("pythn", known.clone()), None);
+        assert_eq!(closest("carrgooo", known), None);
+    }
+
+    #[test]
+    fn suggestion_prefers_the_closest() {
+        let known = names(&["cat", "cut", "cap"]);
+        assert_eq!(closest("cat", known.clone()), Some("cat".to_string()));
+        // Ties go to the name sorting first.
+        assert_eq!(closest("cbt", known), Some("cat".to_string()));
+    }
+}
@@ -0,0 +1,1039 @@
+use lib_app::*;
+use lib_ui::*;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::unix::pipe;
+
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+use tui::backend::{Backend, TestBackend};
+use tui::Terminal;
+
+use async_process::unix::CommandExt;
+use async_process::{Child, Command, Stdio};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{self, PipeReader, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::builtins::*;
+use crate::dispatch;
+use crate::error::*;
+use crate::expand::*;
+use crate::parser::{self, *};
+
+/// Where one of a child's standard streams is connected.
+pub enum Target {
+    Inherit,
+    Null,
+    Fd(OwnedFd),
+}
+
+impl Target {
+    fn try_clone(&self) -> io::Result<Target> {
+        match self {
+            Target::Inherit => Ok(Target::Inherit),
+            Target::Null => Ok(Target::Null),
+            Target::Fd(fd) => Ok(Target::Fd(fd.try_clone()?)),
+        }
+    }
+
+    fn into_stdio(self) -> Stdio {
+        match self {
+            Target::Inherit => Stdio::inherit(),
+            Target::Null => Stdio::null(),
+            Target::Fd(fd) => Stdio::from(fd),
+        }
+    }
+}
+
+/// The stdin, stdout and stderr of a child.
+pub type Fds = [Target; 3];
+
+fn here_string(text: String) -> io::Result<Target> {
+    let (reader, mut writer) = io::pipe()?;
+    // Write from a thread so a long string cannot fill the pipe and block us.
+    std::thread::spawn(move || writer.write_all(text.as_bytes()));
+    Ok(Target::Fd(reader.into()))
+}
+
+fn open_redirect(redirect: &Redirect, fds: &Fds, app: &App) -> io::Result<Target> {
+    let path = expand_word(&redirect.target, app);
+    // A subshell shares the process' directory, so files are opened from
+    // its own.
+    let file = Path::new(&app.path).join(&path);
+    let file = match redirect.op {
+        RedirectOp::Read => File::open(&file)?,
+        RedirectOp::Write | RedirectOp::WriteAll => File::create(&file)?,
+        RedirectOp::Append | RedirectOp::AppendAll => {
+            OpenOptions::new().append(true).create(true).open(&file)?
+        }
+        RedirectOp::HereString => return here_string(path + "\n"),
+        RedirectOp::DupOut | RedirectOp::DupIn => {
+            return match path.as_str() {
+                "-" => Ok(Target::Null),
+                "0" => fds[0].try_clone(),
+                "1" => fds[1].try_clone(),
+                "2" => fds[2].try_clone(),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "bad file descriptor",
+                )),
+            };
+        }
+    };
+    Ok(Target::Fd(file.into()))
+}
+
+/// Apply the redirections of `command`, in order, on top of `fds`.
+pub fn redirect(command: &SimpleCommand, mut fds: Fds, app: &App) -> Result<Fds, String> {
+    for redirect in command.redirects.iter() {
+        let fd = redirect.fd as usize;
+        if fd > 2 {
+            return Err(format!("{}: bad file descriptor", redirect.fd));
+        }
+        let target = open_redirect(redirect, &fds, app)
+            .map_err(|err| format!("{}: {}", redirect.target.text(), err))?;
+        if matches!(redirect.op, RedirectOp::WriteAll | RedirectOp::AppendAll) {
+            fds[2] = target.try_clone().map_err(|err| err.to_string())?;
+        }
+        fds[fd] = target;
+    }
+    Ok(fds)
+}
+
+/// Spawn `command` with the given streams in the process group `pgid`, or
+/// in a new group led by the child when `pgid` is 0.
+fn spawn(command: &mut Command, fds: Fds, pgid: u32) -> io::Result<Child> {
+    let [stdin, stdout, stderr] = fds;
+    command
+        .stdin(stdin.into_stdio())
+        .stdout(stdout.into_stdio())
+        .stderr(stderr.into_stdio());
+    unsafe {
+        command.pre_exec(move || {
+            libc::setpgid(0, pgid as libc::pid_t);
+            Ok(())
+        });
+    }
+
+    let child = command.spawn()?;
+    // Also set it from our side, so signals sent right away reach the group.
+    let pgid = if pgid == 0 { child.id() } else { pgid };
+    unsafe {
+        libc::setpgid(child.id() as libc::pid_t, pgid as libc::pid_t);
+    }
+    Ok(child)
+}
+
+pub fn run_command(
+    command: &SimpleCommand,
+    app: &mut App,
+    fds: Fds,
+    pgid: u32,
+) -> Result<Child, CommandError> {
+    let mut words = expand_command(command, app).into_iter();
+    let cmd = words.next().unwrap_or_default();
+    let args: Vec<String> = words.collect();
+
+    let assignments: Vec<(String, String)> = command
+        .assignments
+        .iter()
+        .map(|a| (a.name.clone(), expand_word(&a.value, app)))
+        .collect();
+    let mut child = Command::new(&cmd);
+    child
+        .args(args)
+        .current_dir(&app.path)
+        .env_clear()
+        .envs(app.exported_vars())
+        .envs(assignments);
+    spawn(&mut child, fds, pgid).map_err(|err| CommandError::from_spawn(&cmd, err, &app.path))
+}
+
+/// A pipeline stage: either a running child, a subshell that sends its
+/// status once it is done, or a status known up front, for example when a
+/// redirection failed.
+pub enum Stage {
+    Running(Child),
+    Subshell(oneshot::Receiver<i32>),
+    Done(i32),
+}
+
+/// A spawned pipeline with the read ends of its last stage's stdout and of
+/// the stderr shared by all stages. All its processes share the group `pgid`,
+/// and its subshells the flag `stop`.
+pub struct RunningPipeline {
+    pub stages: Vec<Stage>,
+    pub pgid: u32,
+    pub stop: Option<Arc<AtomicBool>>,
+    pub stdout: Option<PipeReader>,
+    pub stderr: Option<PipeReader>,
+}
+
+/// `line` of the Outputs panel without its markup.
+fn plain_text(line: &str) -> String {
+    let tags = [
+        "<h1>", "</h1>", "<h2>", "</h2>", "<c>", "</c>", "<i>", "</i>", "<e>", "</e>",
+    ];
+    let text = tags
+        .iter()
+        .fold(line.to_string(), |text, tag| text.replace(tag, ""));
+    unescape_markup(&text)
+}
+
+/// What a builtin or a function printed in the Outputs panel, as the text
+/// of its stdout and of its stderr without the markup. Errors go to stderr.
+fn split_output(output: &str) -> (String, String) {
+    let (mut out, mut err) = (String::new(), String::new());
+    for line in output.lines() {
+        let text = match line.starts_with("<c>") || line.starts_with("<e>") {
+            true => &mut err,
+            false => &mut out,
+        };
+        text.push_str(&plain_text(line));
+        text.push('\n');
+    }
+    (out, err)
+}
+
+/// Write what a builtin or a function printed to the stdout and stderr of
+/// its stage.
+fn write_output(output: String, fds: Fds) {
+    let [_, stdout, stderr] = fds;
+    let (out, err) = split_output(&output);
+    // Write from a thread, as the next stage only reads once it is spawned.
+    std::thread::spawn(move || {
+        for (target, text) in [(stdout, out), (stderr, err)] {
+            if let Target::Fd(fd) = target {
+                let _ = File::from(fd).write_all(text.as_bytes());
+            }
+        }
+    });
+}
+
+/// Whether `command` calls a function or a builtin, which run inside the
+/// shell.
+fn runs_in_shell(command: &SimpleCommand, app: &App) -> bool {
+    let words = expand_command(command, app);
+    is_builtin(&words)
+        || words
+            .first()
+            .is_some_and(|name| app.functions.contains_key(name))
+}
+
+/// `command` without its redirections, as a pipeline of its own.
+fn shell_stage(command: &SimpleCommand, app: &App) -> Pipeline {
+    Pipeline {
+        commands: vec![SimpleCommand {
+            redirects: Vec::new(),
+            ..command.clone()
+        }],
+        background: false,
+        text: expand_command(command, app).join(" "),
+    }
+}
+
+/// Run `command`, a function or a builtin with redirections, inside the
+/// shell with the streams `fds`. What it prints is written to them once it
+/// is done, and its status returned.
+async fn run_in_shell<B: Backend>(
+    command: &SimpleCommand,
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+    fds: Fds,
+) -> i32 {
+    let [stdin, stdout, stderr] = fds;
+    let stdin = match stdin {
+        Target::Fd(fd) => Some(fd),
+        _ => None,
+    };
+    let stdin = std::mem::replace(&mut app.stdin, stdin);
+    let output = std::mem::take(&mut app.output);
+    // Boxed, as the stage may itself start pipelines.
+    Box::pin(execute_pipeline(&shell_stage(command, app), app, terminal)).await;
+    let captured = std::mem::replace(&mut app.output, output);
+    app.stdin = stdin;
+    write_output(captured, [Target::Null, stdout, stderr]);
+    app.last_status()
+}
+
+/// Run `command`, a function or a builtin, in a subshell with the streams
+/// `fds`, so that it runs along with the other stages of its pipeline or
+/// in the background. Its status is sent once it is done.
+fn start_subshell(
+    command: &SimpleCommand,
+    app: &App,
+    fds: Fds,
+    stop: Arc<AtomicBool>,
+) -> oneshot::Receiver<i32> {
+    let [stdin, stdout, stderr] = fds;
+    let file = |target: Target| match target {
+        Target::Fd(fd) => Some(File::from(fd)),
+        _ => None,
+    };
+    let mut shell = App {
+        config: app.config.clone(),
+        input_mode: InputMode::Output,
+        path: app.path.clone(),
+        dir_stack: app.dir_stack.clone(),
+        history: app.history.clone(),
+        status: app.status,
+        pipefail: app.pipefail,
+        vars: app.vars.clone(),
+        aliases: app.aliases.clone(),
+        functions: app.functions.clone(),
+        positional: app.positional.clone(),
+        frames: app.frames.clone(),
+        stdin: match stdin {
+            Target::Fd(fd) => Some(fd),
+            _ => None,
+        },
+        subshell: Some(Subshell {
+            stdout: file(stdout),
+            stderr: file(stderr),
+            stop,
+            stopped: None,
+        }),
+        ..App::default()
+    };
+    let stage = shell_stage(command, app);
+
+    let (status_tx, status_rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build();
+        let code = match runtime {
+            Ok(runtime) => runtime.block_on(run_subshell(&stage, &mut shell)),
+            Err(_) => 1,
+        };
+        let _ = status_tx.send(code);
+    });
+    status_rx
+}
+
+/// Run `stage` in the subshell `app` and return its status.
+async fn run_subshell(stage: &Pipeline, app: &mut App) -> i32 {
+    // Nothing is drawn, as in the rc file.
+    let mut terminal = match Terminal::new(TestBackend::new(80, 24)) {
+        Ok(terminal) => terminal,
+        Err(_) => return 1,
+    };
+    execute_pipeline(stage, app, &mut terminal).await;
+    if !app.output.is_empty() && !app.output.ends_with('\n') {
+        app.output.push('\n');
+    }
+    update_subshell(app);
+    let stopped = app.subshell.as_ref().and_then(|subshell| subshell.stopped);
+    stopped.unwrap_or(app.last_status())
+}
+
+/// In a subshell, write the lines it printed so far to its streams, and
+/// once the shell stopped it or its stdout is closed, interrupt what runs
+/// as a signal would. A stopped subshell writes nothing more.
+pub fn update_subshell(app: &mut App) {
+    let subshell = match app.subshell.as_mut() {
+        Some(subshell) => subshell,
+        None => return,
+    };
+    if subshell.stopped.is_some() {
+        app.output.clear();
+        return;
+    }
+    if let Some(end) = app.output.rfind('\n') {
+        let output: String = app.output.drain(..=end).collect();
+        let (out, err) = split_output(&output);
+        if let Some(stderr) = subshell.stderr.as_mut() {
+            let _ = stderr.write_all(err.as_bytes());
+        }
+        if let Some(stdout) = subshell.stdout.as_mut() {
+            if stdout.write_all(out.as_bytes()).is_err() {
+                subshell.stopped = Some(128 + libc::SIGPIPE);
+            }
+        }
+    }
+    if subshell.stop.load(Ordering::Relaxed) && subshell.stopped.is_none() {
+        subshell.stopped = Some(128 + libc::SIGINT);
+    }
+    if let Some(code) = subshell.stopped {
+        app.interrupted = true;
+        signal_foreground(app, code - 128);
+    }
+}
+
+/// Spawn every stage of `pipeline`, feeding each child's stdout into the
+/// stdin of the next one. The last stage's stdout and every stage's stderr
+/// go into pipes read by `read_output`, unless they are redirected elsewhere.
+/// Functions and builtins run in subshells instead, writing to their stage's
+/// streams as they go, or inside the shell when they are the only stage.
+pub async fn run_pipeline<B: Backend>(
+    pipeline: &Pipeline,
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+) -> RunningPipeline {
+    let mut stages: Vec<Stage> = Vec::new();
+    let mut pgid = 0;
+    let mut stop: Option<Arc<AtomicBool>> = None;
+    // The terminal belongs to the UI, so children never read from it. In
+    // a function run with its stdin redirected, they read that instead.
+    let mut upstream = match app.stdin.as_ref().map(|stdin| stdin.try_clone()) {
+        Some(Ok(stdin)) => Target::Fd(stdin),
+        _ => Target::Null,
+    };
+    let (stderr, stderr_writer) = match io::pipe() {
+        Ok((reader, writer)) => (Some(reader), Target::Fd(writer.into())),
+        Err(_) => (None, Target::Inherit),
+    };
+
+    for command in pipeline.commands.iter() {
+        let stdin = std::mem::replace(&mut upstream, Target::Null);
+        let stdout = match io::pipe() {
+            Ok((reader, writer)) => {
+                upstream = Target::Fd(reader.into());
+                Target::Fd(writer.into())
+            }
+            Err(_) => Target::Null,
+        };
+
+        let stderr = stderr_writer.try_clone().unwrap_or(Target::Null);
+
+        match redirect(command, [stdin, stdout, stderr], app) {
+            Ok(_) if expand_command(command, app).is_empty() => stages.push(Stage::Done(0)),
+            Ok(fds) if runs_in_shell(command, app) => {
+                if pipeline.commands.len() == 1 && !pipeline.background {
+                    let code = run_in_shell(command, app, terminal, fds).await;
+                    stages.push(Stage::Done(code));
+                } else {
+                    let stop = stop.get_or_insert_with(Arc::default).clone();
+                    stages.push(Stage::Subshell(start_subshell(command, app, fds, stop)));
+                }
+            }
+            Ok(fds) => match run_command(command, app, fds, pgid) {
+                Ok(child) => {
+                    if pgid == 0 {
+                        pgid = child.id();
+                    }
+                    stages.push(Stage::Running(child));
+                }
+                Err(err) => {
+                    report_error(app, &err);
+                    stages.push(Stage::Done(err.status()));
+                }
+            },
+            Err(err) => {
+                app.output
+                    .push_str(&format!("<c>{}</c>\n", escape_markup(&err.to_string())));
+                stages.push(Stage::Done(1));
+            }
+        }
+    }
+
+    let stdout = match upstream {
+        Target::Fd(fd) => Some(PipeReader::from(fd)),
+        _ => None,
+    };
+    RunningPipeline {
+        stages,
+        pgid,
+        stop,
+        stdout,
+        stderr,
+    }
+}
+
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => 128 + status.signal().unwrap_or(0),
+    }
+}
+
+fn receiver(reader: Option<PipeReader>) -> Option<BufReader<pipe::Receiver>> {
+    let receiver = pipe::Receiver::from_owned_fd(reader?.into()).ok()?;
+    Some(BufReader::new(receiver))
+}
+
+/// Read a line from `reader`, or wait forever once it is closed.
+async fn read_line(reader: &mut Option<BufReader<pipe::Receiver>>, line: &mut Vec<u8>) -> usize {
+    match reader {
+        Some(reader) => reader.read_until(b'\n', line).await.unwrap_or(0),
+        None => std::future::pending().await,
+    }
+}
+
+fn line_text(line: &[u8]) -> String {
+    let text = String::from_utf8_lossy(line);
+    text.trim_end_matches('\n').to_string()
+}
+
+/// Stream the pipeline's stdout and stderr to the main loop, in the order
+/// lines arrive, then wait for every stage. The status is the last stage's
+/// one, or with `pipefail` the last non-zero status in the pipeline, along
+/// with the signal that killed that stage, if any.
+async fn read_output(
+    id: u32,
+    mut pipeline: RunningPipeline,
+    pipefail: bool,
+    events: UnboundedSender<ExecEvent>,
+) {
+    let mut stdout = receiver(pipeline.stdout.take());
+    let mut stderr = receiver(pipeline.stderr.take());
+    let mut out_line: Vec<u8> = Vec::new();
+    let mut err_line: Vec<u8> = Vec::new();
+
+    while stdout.is_some() || stderr.is_some() {
+        tokio::select! {
+            n = read_line(&mut stdout, &mut out_line) => {
+                if n == 0 {
+                    stdout = None;
+                    continue;
+                }
+                let line = line_text(&out_line);
+                let _ = events.send(ExecEvent::Output { id, line, stderr: false });
+                out_line.clear();
+            }
+            n = read_line(&mut stderr, &mut err_line) => {
+                if n == 0 {
+                    stderr = None;
+                    continue;
+                }
+                let line = line_text(&err_line);
+                let _ = events.send(ExecEvent::Output { id, line, stderr: true });
+                err_line.clear();
+            }
+        }
+    }
+
+    let (mut code, mut signal) = (0, None);
+    for stage in pipeline.stages.iter_mut() {
+        let (stage_code, stage_signal) = match stage {
+            Stage::Running(child) => match child.status().await {
+                Ok(status) => (exit_code(status), status.signal()),
+                Err(_) => (1, None),
+            },
+            Stage::Subshell(status) => (status.await.unwrap_or(1), None),
+            Stage::Done(code) => (*code, None),
+        };
+        if !pipefail || stage_code != 0 {
+            (code, signal) = (stage_code, stage_signal);
+        }
+    }
+
+    let _ = events.send(ExecEvent::Exit { id, code, signal });
+}
+
+/// Spawn `pipeline` and hand it to a task streaming its output back to the
+/// main loop. The pipeline is added to the job table and its number returned.
+pub async fn start_pipeline<B: Backend>(
+    pipeline: &Pipeline,
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+) -> usize {
+    let running = run_pipeline(pipeline, app, terminal).await;
+    let id = app.next_id;
+    app.next_id += 1;
+
+    let pids = running
+        .stages
+        .iter()
+        .filter_map(|stage| match stage {
+            Stage::Running(child) => Some(child.id()),
+            Stage::Subshell(_) | Stage::Done(_) => None,
+        })
+        .collect();
+    let number = app.add_job(id, running.pgid, pids, pipeline.text.clone());
+    if let Some(job) = app.job_mut(number) {
+        job.stop = running.stop.clone();
+    }
+
+    tokio::spawn(read_output(
+        id,
+        running,
+        app.pipefail,
+        app.events_tx.clone(),
+    ));
+    number
+}
+
+fn markup_line(line: &str, stderr: bool) -> String {
+    let line = escape_markup(line);
+    if stderr {
+        format!("<e>{}</e>\n", line)
+    } else {
+        format!("{}\n", line)
+    }
+}
+
+/// Handle the messages sent by pipeline tasks so far: buffer output in the
+/// jobs, mirror the foreground job in the Outputs panel and report background
+/// jobs that finished. Returns whether anything changed.
+pub fn drain_events(app: &mut App) -> bool {
+    let mut changed = false;
+
+    while let Ok(event) = app.events_rx.try_recv() {
+        changed = true;
+        match event {
+            ExecEvent::Output { id, line, stderr } => {
+                let text = markup_line(&line, stderr);
+                match app.jobs.iter_mut().find(|job| job.id == id) {
+                    Some(job) => {
+                        job.output.push_str(&text);
+                        if app.foreground == Some(job.number) {
+                            app.output.push_str(&text);
+                        }
+                    }
+                    None => app.output.push_str(&text),
+                }
+            }
+            ExecEvent::Exit { id, code, signal } => {
+                if let Some(job) = app.jobs.iter_mut().find(|job| job.id == id) {
+                    job.state = JobState::Done { code, signal };
+                    if app.foreground != Some(job.number) {
+                        app.output
+                            .push_str(&format!("<i>{}</i>\n", job_line(job, false)));
+                    }
+                }
+            }
+        }
+    }
+
+    changed
+}
+
+/// Send `signal` to the process group of `job`. Its subshells have no
+/// process, so a signal that would end one stops them instead.
+pub fn signal_job(job: &Job, signal: i32) {
+    if job.pgid != 0 {
+        unsafe {
+            libc::killpg(job.pgid as libc::pid_t, signal);
+        }
+    }
+    let ends = !matches!(
+        signal,
+        0 | libc::SIGCONT
+            | libc::SIGSTOP
+            | libc::SIGTSTP
+            | libc::SIGTTIN
+            | libc::SIGTTOU
+            | libc::SIGCHLD
+            | libc::SIGURG
+            | libc::SIGWINCH
+    );
+    if let Some(stop) = job.stop.as_ref().filter(|_| ends) {
+        stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Send `signal` to the foreground job.
+pub fn signal_foreground(app: &mut App, signal: i32) {
+    let job = match app.foreground.and_then(|number| app.job_mut(number)) {
+        Some(job) if job.pgid != 0 || job.stop.is_some() => job,
+        _ => return,
+    };
+    signal_job(job, signal);
+
+    if signal == libc::SIGTSTP {
+        // A stopped job never exits, so give the prompt back.
+        job.state = JobState::Stopped;
+        let line = format!("<i>{}</i>\n", job_line(job, false));
+        app.output.push_str(&line);
+        app.foreground = None;
+        app.input_mode = InputMode::Editing;
+    }
+}
+
+/// Let the foreground job run in the background and give the prompt back.
+pub fn detach_foreground(app: &mut App) {
+    if let Some(job) = app.foreground.take().and_then(|number| app.job(number)) {
+        let line = format!("<i>{} &</i>\n", job_line(job, false));
+        app.output.push_str(&line);
+    }
+    app.input_mode = InputMode::Editing;
+}
+
+/// Wait until every job in `jobs` is done, still handling keys and redrawing
+/// as output arrives, then remove them from the job table. Returns the status
+/// of the last one, or `None` if the user gave the prompt back first.
+async fn wait_jobs<B: Backend>(
+    jobs: &[usize],
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+) -> Option<(i32, Option<i32>)> {
+    loop {
+        if jobs
+            .iter()
+            .all(|number| app.job(*number).is_none_or(|job| job.is_done()))
+        {
+            let mut status = (0, None);
+            for number in jobs.iter() {
+                if let Some(job) = app.remove_job(*number) {
+                    if let JobState::Done { code, signal } = job.state {
+                        status = (code, signal);
+                    }
+                }
+                if app.foreground == Some(*number) {
+                    app.foreground = None;
+                }
+            }
+            let _ = terminal.draw(|f| ui(f, app));
+            return Some(status);
+        }
+
+        let _ = dispatch(app, terminal, Duration::from_millis(20));
+        // Without a foreground job the interrupt key has no process to
+        // signal, so it stops the wait itself and the jobs keep running.
+        if app.interrupted && app.foreground.is_none() {
+            return Some((130, None));
+        }
+        if app.quit || !matches!(app.input_mode, InputMode::Output) {
+            return None;
+        }
+        tokio::task::yield_now().await;
+    }
+}
+
+/// How running a list of commands ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    /// Every command that had to run did.
+    Normal,
+    /// `return` left the running function.
+    Return,
+    /// `break` left this many enclosing loops.
+    Break(usize),
+    /// `continue` went on with the next iteration of the `n`th enclosing
+    /// loop.
+    Continue(usize),
+    /// The user gave the prompt back or interrupted a job, so nothing else
+    /// runs.
+    Stop,
+}
+
+/// Function calls nested deeper than this fail, instead of recursing
+/// until the shell runs out of memory.
+const MAX_CALL_DEPTH: usize = 200;
+
+fn set_status(app: &mut App, code: i32, start: Instant) {
+    app.status = Some(CommandStatus {
+        code,
+        signal: None,
+        duration: start.elapsed(),
+    });
+}
+
+/// Run `pipeline` as a call of the function it names, if it is a single
+/// command naming one without redirections. The words after the name
+/// become the positional parameters.
+async fn call_function<B: Backend>(
+    pipeline: &Pipeline,
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+) -> Option<Flow> {
+    if pipeline.commands.len() != 1
+        || pipeline.background
+        || !pipeline.commands[0].redirects.is_empty()
+    {
+        return None;
+    }
+    let mut words = expand_command(&pipeline.commands[0], app);
+    let body = app.functions.get(words.first()?)?.clone();
+    let start = Instant::now();
+
+    if app.frames.len() >= MAX_CALL_DEPTH {
+        let message = format!(
+            "<c>{}: maximum function nesting level exceeded ({})</c>\n",
+            escape_markup(&words[0]),
+            MAX_CALL_DEPTH
+        );
+        app.output.push_str(&message);
+        set_status(app, 1, start);
+        return Some(Flow::Normal);
+    }
+    let list = match parse_aliased(&body, &app.aliases) {
+        Ok(list) => list,
+        Err(err) => {
+            app.output.push_str(&format!(
+                "<c>{}</c>\n",
+                escape_markup(&format!("{}: {}", words[0], err))
+            ));
+            set_status(app, 1, start);
+            return Some(Flow::Normal);
+        }
+    };
+
+    app.push_frame(words.split_off(1));
+    let flow = run_list(&list, app, terminal).await;
+    app.pop_frame();
+    match flow {
+        Flow::Return => Some(Flow::Normal),
+        flow => Some(flow),
+    }
+}
+
+/// Run `pipeline` and record how it finished: a function call or a
+/// builtin runs in the shell, anything else is spawned as a job and waited
+/// for unless it runs in the background.
+async fn execute_pipeline<B: Backend>(
+    pipeline: &Pipeline,
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+) -> Flow {
+    if let Some(flow) = call_function(pipeline, app, terminal).await {
+        return flow;
+    }
+
+    let start = Instant::now();
+    let jobs = match run_builtin(pipeline, app) {
+        Some(Builtin::Status(code)) => {
+            set_status(app, code, start);
+            return Flow::Normal;
+        }
+        Some(Builtin::Return(code)) => {
+            set_status(app, code, start);
+            return Flow::Return;
+        }
+        Some(Builtin::Break(n)) => {
+            set_status(app, 0, start);
+            return Flow::Break(n);
+        }
+        Some(Builtin::Continue(n)) => {
+            set_status(app, 0, start);
+            return Flow::Continue(n);
+        }
+        Some(Builtin::Exit(code)) => {
+            set_status(app, code, start);
+            app.quit = true;
+            return Flow::Stop;
+        }
+        Some(Builtin::Wait { jobs, last }) => (jobs, last),
+        None if pipeline.background => {
+            let number = start_pipeline(pipeline, app, terminal).await;
+            let line = match app.job(number).map_or(0, |job| job.pgid) {
+                // Only functions and builtins, in subshells.
+                0 => format!("<i>[{}]</i>\n", number),
+                pgid => format!("<i>[{}] {}</i>\n", number, pgid),
+            };
+            app.output.push_str(&line);
+            return Flow::Normal;
+        }
+        None => {
+            let number = start_pipeline(pipeline, app, terminal).await;
+            app.foreground = Some(number);
+            (vec![number], true)
+        }
+    };
+
+    let (code, signal) = match wait_jobs(&jobs.0, app, terminal).await {
+        Some(status) if jobs.1 || app.interrupted => status,
+        Some(_) => (0, None),
+        None => return Flow::Stop,
+    };
+    if let Some(signal) = signal {
+        app.output
+            .push_str(&format!("<c>Terminated by {}</c>\n", signal_name(signal)));
+    }
+    app.status = Some(CommandStatus {
+        code,
+        signal,
+        duration: start.elapsed(),
+    });
+    Flow::Normal
+}
+
+/// Run a pipeline or a compound command. The status of a compound command
+/// is the one of the last command it ran, or 0 when it ran none. Nothing
+/// runs once the user interrupted the command line or gave the prompt back.
+async fn execute<B: Backend>(
+    command: &parser::Command,
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+) -> Flow {
+    if app.interrupted || app.quit || !matches!(app.input_mode, InputMode::Output) {
+        return Flow::Stop;
+    }
+    let start = Instant::now();
+
+    match command {
+        parser::Command::Pipeline(pipeline) => {
+            return execute_pipeline(pipeline, app, terminal).await
+        }
+        parser::Command::Group(list) => return run_list(list, app, terminal).await,
+        parser::Command::If {
+            branches,
+            otherwise,
+        } => {
+            for (condition, body) in branches.iter() {
+                let flow = run_list(condition, app, terminal).await;
+                if flow != Flow::Normal {
+                    return flow;
+                }
+                if app.last_status() == 0 {
+                    return run_list(body, app, terminal).await;
+                }
+            }
+            if let Some(otherwise) = otherwise {
+                return run_list(otherwise, app, terminal).await;
+            }
+        }
+        parser::Command::Loop {
+            until,
+            condition,
+            body,
+        } => {
+            app.loops += 1;
+            let flow = run_loop(*until, condition, body, app, terminal).await;
+            app.loops -= 1;
+            return flow;
+        }
+        parser::Command::For { name, words, body } => {
+            let values: Vec<String> = match words {
+                Some(words) => words
+                    .iter()
+                    .flat_map(|word| expand_fields(word, app))
+                    .collect(),
+                None => app.positional.clone(),
+            };
+            app.loops += 1;
+            let flow = run_for(name, values, body, app, terminal).await;
+            app.loops -= 1;
+            return flow;
+        }
+        parser::Command::Case { word, arms } => {
+            let value = expand_word(word, app);
+            let arm = arms.iter().find(|arm| {
+                arm.patterns
+                    .iter()
+                    .any(|pattern| pattern_matches(&expand_pattern(pattern, app), &value))
+            });
+            if let Some(arm) = arm {
+                set_status(app, 0, start);
+                return run_list(&arm.body, app, terminal).await;
+            }
+        }
+        parser::Command::Function { name, body } => {
+            app.functions.insert(name.clone(), body.clone());
+        }
+    }
+
+    set_status(app, 0, start);
+    Flow::Normal
+}
+
+/// What a loop does once its condition or body ended with `flow`: `None`
+/// when it goes on, or else how the loop itself ends. A `break` or a
+/// `continue` for an outer loop is passed on with one loop less.
+fn leave_loop(flow: Flow) -> Option<Flow> {
+    match flow {
+        Flow::Normal | Flow::Continue(1) => None,
+        Flow::Break(1) => Some(Flow::Normal),
+        Flow::Break(n) => Some(Flow::Break(n - 1)),
+        Flow::Continue(n) => Some(Flow::Continue(n - 1)),
+        flow => Some(flow),
+    }
+}
+
+/// Run a `while` loop, or an `until` loop when `until` is set.
+async fn run_loop<B: Backend>(
+    until: bool,
+    condition: &List,
+    body: &List,
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+) -> Flow {
+    let start = Instant::now();
+    let mut code = 0;
+    loop {
+        // A loop may run only builtins, and no job would see the keys.
+        let _ = dispatch(app, terminal, Duration::ZERO);
+        let flow = run_list(condition, app, terminal).await;
+        match leave_loop(flow) {
+            Some(Flow::Normal) => break,
+            Some(flow) => return flow,
+            None if flow == Flow::Continue(1) => continue,
+            None => {}
+        }
+        if (app.last_status() == 0) == until {
+            break;
+        }
+        let flow = run_list(body, app, terminal).await;
+        code = app.last_status();
+        match leave_loop(flow) {
+            Some(Flow::Normal) => break,
+            Some(flow) => return flow,
+            None => {}
+        }
+    }
+    set_status(app, code, start);
+    Flow::Normal
+}
+
+/// Run a `for` loop, setting `name` to each of `values` in turn.
+async fn run_for<B: Backend>(
+    name: &str,
+    values: Vec<String>,
+    body: &List,
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+) -> Flow {
+    let start = Instant::now();
+    let mut code = 0;
+    for value in values {
+        let _ = dispatch(app, terminal, Duration::ZERO);
+        app.set_var(name, value);
+        let flow = run_list(body, app, terminal).await;
+        code = app.last_status();
+        match leave_loop(flow) {
+            Some(Flow::Normal) => break,
+            Some(flow) => return flow,
+            None => {}
+        }
+    }
+    set_status(app, code, start);
+    Flow::Normal
+}
+
+/// Run the commands of an and-or list, each one depending on the status
+/// of the previous one.
+async fn run_and_or<B: Backend>(and_or: &AndOr, app: &mut App, terminal: &mut Terminal<B>) -> Flow {
+    let mut flow = execute(&and_or.first, app, terminal).await;
+    for (connector, command) in and_or.rest.iter() {
+        if flow != Flow::Normal {
+            break;
+        }
+        let succeeded = app.last_status() == 0;
+        if succeeded == (*connector == Connector::And) {
+            flow = execute(command, app, terminal).await;
+        }
+    }
+    flow
+}
+
+/// Run every item of `list` in turn, recording how each one finished.
+/// Stops early if the user gives the prompt back while a job runs, or when
+/// `return`, `break` or `continue` leaves it.
+pub fn run_list<'a, B: Backend>(
+    list: &'a List,
+    app: &'a mut App,
+    terminal: &'a mut Terminal<B>,
+) -> Pin<Box<dyn Future<Output = Flow> + 'a>> {
+    // Boxed, as compound commands and functions run lists recursively.
+    Box::pin(async move {
+        for item in list.items.iter() {
+            let flow = run_and_or(item, app, terminal).await;
+            if flow != Flow::Normal {
+                return flow;
+            }
+        }
+        Flow::Normal
+    })
+}
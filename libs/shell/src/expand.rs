@@ -0,0 +1,216 @@
+use lib_app::*;
+
+use crate::parser::*;
+
+use std::borrow::Cow;
+use std::ffi::{CStr, CString};
+
+/// The home directory of `user` from the password database, or of the
+/// current user when `user` is `None`.
+fn passwd_home(user: Option<&str>) -> Option<String> {
+    let entry = match user {
+        Some(user) => {
+            let name = CString::new(user).ok()?;
+            unsafe { libc::getpwnam(name.as_ptr()) }
+        }
+        None => unsafe { libc::getpwuid(libc::getuid()) },
+    };
+    if entry.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr((*entry).pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
+
+/// The directory `~` (empty `user`) or `~user` stands for.
+pub fn home_dir(user: &str, app: &App) -> Option<String> {
+    match user {
+        "" => app
+            .var("HOME")
+            .map(|home| home.to_string())
+            .or_else(|| passwd_home(None)),
+        user => passwd_home(Some(user)),
+    }
+}
+
+/// Replace a leading unquoted `~` or `~user` with the home directory.
+fn expand_tilde<'a>(parts: &'a [WordPart], app: &App) -> Cow<'a, [WordPart]> {
+    let first = match parts.first() {
+        Some(WordPart::Bare(first)) if first.starts_with('~') => first,
+        _ => return Cow::Borrowed(parts),
+    };
+    let (user, rest) = match first.find('/') {
+        Some(slash) => first[1..].split_at(slash - 1),
+        None => (&first[1..], ""),
+    };
+    let home = match home_dir(user, app) {
+        Some(home) => home,
+        None => return Cow::Borrowed(parts),
+    };
+
+    let mut expanded = vec![WordPart::Quoted(home)];
+    if !rest.is_empty() {
+        expanded.push(WordPart::Bare(rest.to_string()));
+    }
+    expanded.extend_from_slice(&parts[1..]);
+    Cow::Owned(expanded)
+}
+
+/// The value of a parameter, or `None` if it is unset.
+fn param_value(name: &str, app: &App) -> Option<String> {
+    match name {
+        "?" => Some(app.last_status().to_string()),
+        "$" => Some(std::process::id().to_string()),
+        "#" => Some(app.positional.len().to_string()),
+        "@" | "*" => Some(app.positional.join(" ")),
+        "0" => Some(String::from("rust_shell")),
+        _ if name.bytes().all(|b| b.is_ascii_digit()) => {
+            let n: usize = name.parse().ok()?;
+            app.positional.get(n.checked_sub(1)?).cloned()
+        }
+        _ => app.var(name).map(|value| value.to_string()),
+    }
+}
+
+/// The value of a parameter part, falling back to its default when the
+/// parameter is unset or empty, as `${NAME:-default}` does.
+fn param_text(name: &str, default: &Option<Vec<WordPart>>, app: &App) -> Option<String> {
+    match (param_value(name, app), default) {
+        (Some(value), _) if !value.is_empty() => Some(value),
+        (_, Some(default)) => Some(expand_parts(default, app)),
+        (value, None) => value,
+    }
+}
+
+fn expand_parts(parts: &[WordPart], app: &App) -> String {
+    let mut text = String::new();
+    for part in parts.iter() {
+        match part {
+            WordPart::Bare(s) | WordPart::Quoted(s) | WordPart::DoubleQuoted(s) => text.push_str(s),
+            WordPart::Param { name, default, .. } => {
+                text.push_str(&param_text(name, default, app).unwrap_or_default())
+            }
+        }
+    }
+    text
+}
+
+/// The text of `word` after expansion, with quotes removed.
+pub fn expand_word(word: &Word, app: &App) -> String {
+    expand_parts(&expand_tilde(&word.parts, app), app)
+}
+
+fn split_parts(
+    parts: &[WordPart],
+    app: &App,
+    fields: &mut Vec<String>,
+    current: &mut Option<String>,
+) {
+    for part in parts.iter() {
+        match part {
+            WordPart::Bare(s) | WordPart::Quoted(s) | WordPart::DoubleQuoted(s) => {
+                current.get_or_insert_with(String::new).push_str(s)
+            }
+            // "$@" makes a field of each positional parameter.
+            WordPart::Param {
+                name, quoted: true, ..
+            } if name == "@" => {
+                for (i, arg) in app.positional.iter().enumerate() {
+                    if i > 0 {
+                        fields.extend(current.take());
+                    }
+                    current.get_or_insert_with(String::new).push_str(arg);
+                }
+            }
+            WordPart::Param {
+                name,
+                default,
+                quoted: true,
+            } => {
+                let value = param_text(name, default, app).unwrap_or_default();
+                current.get_or_insert_with(String::new).push_str(&value);
+            }
+            WordPart::Param {
+                name,
+                default: Some(default),
+                quoted: false,
+            } if param_value(name, app).is_none_or(|value| value.is_empty()) => {
+                split_parts(default, app, fields, current)
+            }
+            WordPart::Param { name, .. } => {
+                let value = param_value(name, app).unwrap_or_default();
+                for (i, piece) in value.split([' ', '\t', '\n']).enumerate() {
+                    if i > 0 {
+                        fields.extend(current.take());
+                    }
+                    if !piece.is_empty() {
+                        current.get_or_insert_with(String::new).push_str(piece);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The fields `word` expands to: unquoted parameters are split on blanks,
+/// `"$@"` gives a field per positional parameter, and a word made only of
+/// empty unquoted parameters disappears.
+pub fn expand_fields(word: &Word, app: &App) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    let mut current: Option<String> = None;
+    split_parts(&expand_tilde(&word.parts, app), app, &mut fields, &mut current);
+    fields.extend(current);
+    fields
+}
+
+/// The command name and arguments of `command` after expansion.
+pub fn expand_command(command: &SimpleCommand, app: &App) -> Vec<String> {
+    command
+        .words
+        .iter()
+        .flat_map(|word| expand_fields(word, app))
+        .collect()
+}
+
+/// `word` expanded as a `case` pattern: the quoted parts match literally.
+pub fn expand_pattern(word: &Word, app: &App) -> String {
+    let escape = |text: &str| {
+        let mut escaped = String::new();
+        for c in text.chars() {
+            if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    let mut pattern = String::new();
+    for part in expand_tilde(&word.parts, app).iter() {
+        match part {
+            WordPart::Bare(s) => pattern.push_str(s),
+            WordPart::Quoted(s) | WordPart::DoubleQuoted(s) => pattern.push_str(&escape(s)),
+            WordPart::Param {
+                name,
+                default,
+                quoted,
+            } => {
+                let value = param_text(name, default, app).unwrap_or_default();
+                match quoted {
+                    true => pattern.push_str(&escape(&value)),
+                    false => pattern.push_str(&value),
+                }
+            }
+        }
+    }
+    pattern
+}
+
+/// Whether `text` matches the glob `pattern`, with `*`, `?` and `[...]`.
+pub fn pattern_matches(pattern: &str, text: &str) -> bool {
+    match (CString::new(pattern), CString::new(text)) {
+        (Ok(pattern), Ok(text)) => unsafe {
+            libc::fnmatch(pattern.as_ptr(), text.as_ptr(), 0) == 0
+        },
+        _ => false,
+    }
+}
@@ -0,0 +1,236 @@
+use lib_app::*;
+
+use crate::parser::*;
+
+/// The source text of each word of `command`.
+fn words(command: &str) -> Vec<&str> {
+    match tokenize(command) {
+        Ok(tokens) => tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Word(word) => Some(&command[word.start..word.end]),
+                _ => None,
+            })
+            .collect(),
+        Err(_) => command.split_whitespace().collect(),
+    }
+}
+
+/// The text after the first word of `command`, as `!*` uses it.
+fn arguments(command: &str) -> String {
+    match words(command).get(1) {
+        Some(first) => {
+            let start = first.as_ptr() as usize - command.as_ptr() as usize;
+            command[start..].trim_end().to_string()
+        }
+        None => String::new(),
+    }
+}
+
+/// The entry an event such as `!!`, `!3`, `!-2` or `!git` refers to.
+fn find_event<'a>(event: &str, history: &'a [HistoryEntry]) -> Option<&'a str> {
+    let entry = match event {
+        "!" => history.last(),
+        event if event.starts_with('-') => {
+            let n: usize = event[1..].parse().ok()?;
+            history.len().checked_sub(n).and_then(|i| history.get(i))
+        }
+        event if event.chars().all(|c| c.is_ascii_digit()) => {
+            let n: usize = event.parse().ok()?;
+            n.checked_sub(1).and_then(|i| history.get(i))
+        }
+        prefix => history
+            .iter()
+            .rev()
+            .find(|entry| entry.command.starts_with(prefix)),
+    };
+    entry.map(|entry| entry.command.as_str())
+}
+
+fn ends_event(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ';' | '&' | '|' | '<' | '>' | '(' | ')' | '\'' | '"')
+}
+
+/// Apply `^old^new^` to the last command.
+fn quick_substitution(line: &str, history: &[HistoryEntry]) -> Result<String, ParseError> {
+    let mut fields = line[1..].splitn(3, '^');
+    let old = fields.next().unwrap_or_default();
+    let new = fields.next().unwrap_or_default();
+    let rest = fields.next().unwrap_or_default();
+
+    let last = match history.last() {
+        Some(entry) => &entry.command,
+        None => return Err(ParseError::EventNotFound(String::from("!!"))),
+    };
+    if old.is_empty() || !last.contains(old) {
+        return Err(ParseError::SubstitutionFailed(line.to_string()));
+    }
+    Ok(last.replacen(old, new, 1) + rest)
+}
+
+/// Replace the csh-style history references of `line`: `!!`, `!n`, `!-n`,
+/// `!prefix`, the last word `!$`, the arguments `!*` and a leading
+/// `^old^new`. Returns `None` when the line has none.
+pub fn expand_history(line: &str, history: &[HistoryEntry]) -> Result<Option<String>, ParseError> {
+    if line.starts_with('^') {
+        return quick_substitution(line, history).map(Some);
+    }
+
+    let mut result = String::new();
+    let mut expanded = false;
+    let mut single_quoted = false;
+    let mut double_quoted = false;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            // Quotes of one kind are plain characters inside the other.
+            '\'' if !double_quoted => single_quoted = !single_quoted,
+            '"' if !single_quoted => double_quoted = !double_quoted,
+            '\\' if !single_quoted => {
+                result.push(c);
+                if let Some((_, next)) = chars.next() {
+                    result.push(next);
+                }
+                continue;
+            }
+            '!' if !single_quoted => {
+                let event = match chars.peek() {
+                    Some(&(_, next)) if next == '!' || next == '$' || next == '*' => {
+                        chars.next();
+                        next.to_string()
+                    }
+                    Some(&(_, next)) if !ends_event(next) && next != '=' => {
+                        let mut event = String::new();
+                        while let Some((_, c)) = chars.next_if(|(_, c)| !ends_event(*c)) {
+                            event.push(c);
+                        }
+                        event
+                    }
+                    _ => {
+                        result.push(c);
+                        continue;
+                    }
+                };
+
+                let text = match event.as_str() {
+                    "$" => history
+                        .last()
+                        .and_then(|entry| words(&entry.command).last().map(|w| w.to_string())),
+                    "*" => history.last().map(|entry| arguments(&entry.command)),
+                    event => find_event(event, history).map(|command| command.to_string()),
+                };
+                match text {
+                    Some(text) => result.push_str(&text),
+                    None => {
+                        let end = chars.peek().map_or(line.len(), |(end, _)| *end);
+                        return Err(ParseError::EventNotFound(line[pos..end].to_string()));
+                    }
+                }
+                expanded = true;
+                continue;
+            }
+            _ => {}
+        }
+        result.push(c);
+    }
+
+    Ok(Some(result).filter(|_| expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(commands: &[&str]) -> Vec<HistoryEntry> {
+        commands
+            .iter()
+            .map(|command| HistoryEntry {
+                command: command.to_string(),
+                timestamp: 0,
+                cwd: String::from("/"),
+                status: Some(0),
+            })
+            .collect()
+    }
+
+    fn expand(line: &str) -> Result<Option<String>, ParseError> {
+        let history = history(&["ls -l /tmp", "git status", "echo one two", "git log"]);
+        expand_history(line, &history)
+    }
+
+    fn expanded(line: &str) -> String {
+        expand(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn last_command() {
+        assert_eq!(expanded("!!"), "git log");
+        assert_eq!(expanded("sudo !!"), "sudo git log");
+        assert_eq!(expanded("!!;ls"), "git log;ls");
+        assert_eq!(expand("echo hi").unwrap(), None);
+        assert!(matches!(
+            expand_history("!!", &[]),
+            Err(ParseError::EventNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn numbered_events() {
+        assert_eq!(expanded("!1"), "ls -l /tmp");
+        assert_eq!(expanded("!3 three"), "echo one two three");
+        assert_eq!(expanded("!-1"), "git log");
+        assert_eq!(expanded("!-3"), "git status");
+        assert!(matches!(expand("!9"), Err(ParseError::EventNotFound(e)) if e == "!9"));
+        assert!(matches!(expand("!0"), Err(ParseError::EventNotFound(_))));
+        assert!(matches!(expand("!-5"), Err(ParseError::EventNotFound(_))));
+    }
+
+    #[test]
+    fn prefix_events() {
+        assert_eq!(expanded("!git"), "git log");
+        assert_eq!(expanded("!ls | wc"), "ls -l /tmp | wc");
+        assert_eq!(expanded("!git st"), "git log st");
+        assert!(matches!(expand("!make"), Err(ParseError::EventNotFound(e)) if e == "!make"));
+    }
+
+    #[test]
+    fn words_of_the_last_command() {
+        assert_eq!(expanded("cat !$"), "cat log");
+        assert_eq!(expanded("echo !*"), "echo log");
+        let history = history(&["echo one two"]);
+        assert_eq!(
+            expand_history("printf !*", &history).unwrap().unwrap(),
+            "printf one two"
+        );
+    }
+
+    #[test]
+    fn quick_substitutions() {
+        assert_eq!(expanded("^log^diff"), "git diff");
+        assert_eq!(expanded("^log^diff^ HEAD"), "git diff HEAD");
+        assert_eq!(expanded("^g^G"), "Git log");
+        assert!(matches!(
+            expand("^push^pull"),
+            Err(ParseError::SubstitutionFailed(_))
+        ));
+        assert!(matches!(
+            expand_history("^a^b", &[]),
+            Err(ParseError::EventNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(expand("echo '!!'").unwrap(), None);
+        assert_eq!(expand("echo \\!!").unwrap(), None);
+        assert_eq!(expanded("echo \"!!\""), "echo \"git log\"");
+        assert_eq!(expanded("echo \"it's !!\""), "echo \"it's git log\"");
+        assert_eq!(
+            expanded("echo \"it's\" '!!' !!"),
+            "echo \"it's\" '!!' git log"
+        );
+        assert_eq!(expand("echo '\"!!\"'").unwrap(), None);
+        assert_eq!(expand("echo ! != !=").unwrap(), None);
+    }
+}
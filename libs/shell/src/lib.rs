@@ -1,273 +1,587 @@
 use crossterm::event;
 use crossterm::event::Event;
 use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyModifiers;
 use crossterm::event::poll;
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::Result;
 
 use lib_app::*;
 use lib_ui::*;
 
-use futures_lite::{io::BufReader, prelude::*};
-
-use tui::backend::Backend;
+use tui::backend::{Backend, TestBackend};
 use tui::Terminal;
 
+use std::collections::hash_map::RandomState;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
 use std::time::Duration;
-use std::io::Read;
-// use std::process::{Command, Stdio, Child};
-use async_process::{Child, Command, Stdio};
 
-fn cd_completion(app: &mut App) {
-    let mut splitted = app.input.trim().split_whitespace().peekable();
-    splitted.next();
+pub mod builtins;
+pub mod completers;
+pub mod completion;
+pub mod error;
+pub mod exec;
+pub mod expand;
+pub mod history;
+pub mod parser;
 
-    let mut arg = String::new();
-    if splitted.peek().is_some() {
-        arg = splitted.next().unwrap().to_string();
-    }
+use completion::*;
+use exec::*;
+use parser::*;
 
-    let mut cmd_path = String::new();
-    cmd_path.push_str(&app.path);
-    cmd_path.push('/');
-    cmd_path.push_str(&arg);
+/// Append the command that just ran to the history file, reporting
+/// failures in the Outputs panel.
+fn save_history(app: &mut App, status: i32) {
+    if let Err(err) = app.save_history(status) {
+        app.output.push_str(&format!("<c>history: {}</c>\n", escape_markup(&err.to_string())));
+    }
+}
 
-    if std::fs::metadata(&cmd_path).is_ok() {
-        let dirs = std::fs::read_dir(&cmd_path).unwrap();
-        for dir in dirs {
-            let dir = dir.unwrap();
-            let path = dir.path();
-            let md = std::fs::metadata(&path).unwrap();
-            if md.is_dir() == true {
-                let name = path.file_name().unwrap().to_str().unwrap();
-                if !name.starts_with(".") {
-                    if path.starts_with(&cmd_path) {
-                        let cmd = "cd ".to_string() + &arg + name + "/";
-                        app.completion.push(cmd.to_string());
-                        app.completion_display.push(name.to_string());
-                    }
-                }
-            }
+/// Run the rc file, `rc` in the configuration directory, one command at a
+/// time. A command goes on over the next lines while it is incomplete, as
+/// a function definition does. It runs before the first frame, so the
+/// terminal given to the commands draws nowhere; their output shows in the
+/// Outputs panel.
+pub async fn run_rc(app: &mut App) {
+    let path = match app.config_dir() {
+        Some(dir) => dir.join("rc"),
+        None => return,
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+        Err(err) => {
+            app.output.push_str(&format!("<c>{}</c>\n", escape_markup(&format!("{}: {}", path.display(), err))));
+            return;
+        }
+    };
+    let mut terminal = match Terminal::new(TestBackend::new(80, 24)) {
+        Ok(terminal) => terminal,
+        Err(_) => return,
+    };
+    let mode = std::mem::replace(&mut app.input_mode, InputMode::Output);
+    let mut source = String::new();
+    let mut number = 0;
+    for line in text.lines() {
+        // `exit` in the rc file exits the shell before it starts.
+        if app.quit {
+            return;
         }
+        number += 1;
+        source.push_str(line);
+        source.push('\n');
+        app.input_mode = InputMode::Output;
+        app.interrupted = false;
+        let err = match parse_aliased(&source, &app.aliases) {
+            Ok(list) => {
+                run_list(&list, app, &mut terminal).await;
+                source.clear();
+                continue;
+            }
+            Err(ParseError::UnexpectedEnd) | Err(ParseError::UnterminatedQuote(_)) => continue,
+            Err(err) => err,
+        };
+        let err = format!("{}:{}: {}", path.display(), number, err);
+        let err = format!("<c>{}</c>\n", escape_markup(&err));
+        app.output.push_str(&err);
+        source.clear();
+    }
+    if let Err(err) = parse_aliased(&source, &app.aliases) {
+        let err = format!("{}:{}: {}", path.display(), number, err);
+        let err = format!("<c>{}</c>\n", escape_markup(&err));
+        app.output.push_str(&err);
     }
+    app.input_mode = mode;
+    app.status = None;
+}
+
+/// The number of columns of the completion menu, which is as wide as the
+/// terminal without the margins.
+fn menu_columns(app: &App) -> usize {
+    let width = crossterm::terminal::size().map_or(80, |(width, _)| width);
+    completion_columns(app, width.saturating_sub(4))
 }
 
-fn create_completion(app: &mut App) {
-    let mut splitted = app.input.trim().split_whitespace();
-    let cmd = splitted.next().unwrap();
+/// Cycle the Outputs panel through the shell output and each job's buffer.
+fn next_output_job(app: &mut App) {
+    let numbers: Vec<usize> = app.jobs.iter().map(|job| job.number).collect();
+    app.output_job = match app.output_job {
+        None => numbers.first().copied(),
+        Some(n) => numbers.iter().skip_while(|m| **m != n).nth(1).copied(),
+    };
+}
 
-    match cmd {
-        "cd" => {
-            cd_completion(app);
+/// Create a file only the user can read in the temporary directory, with
+/// a random name so that nothing can be waiting there for it.
+fn create_temp_file() -> io::Result<(PathBuf, File)> {
+    let mut attempts = 0;
+    loop {
+        let suffix = RandomState::new().build_hasher().finish();
+        let name = format!("rust_shell-{}-{:016x}.sh", std::process::id(), suffix);
+        let path = std::env::temp_dir().join(name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path);
+        match file {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempts < 10 => {
+                attempts += 1
+            }
+            Err(err) => return Err(err),
         }
-        _ => {}
     }
 }
 
-pub fn run_command(command: String, app: &mut App) -> Child {
-    let mut splitted = command.trim().split_whitespace();
-    let cmd = splitted.next().unwrap();
-
-    match cmd {
-        "cd" => {
-            let args = splitted;
-            let path = args.peekable().peek().map_or("/Users/", |x| *x);
-            std::env::set_current_dir(path).unwrap();
-            app.path = std::env::current_dir()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string();
-
-            let child: Child = match Command::new(cmd).stdout(Stdio::piped()).spawn()
-            {
-                Ok(child) => child,
-                Err(_) => {
-                    panic!("failed to execute process");
-                }
-            };
+/// Join the lines of `text` into one command line. A line goes on with
+/// the next one after a trailing `\`, after an operator such as `|` or
+/// `&&`, or while the parser finds a construct left open, such as an `if`
+/// without its `fi`. Other lines are separate commands, joined with `;`.
+/// The lines are kept as written, so that quoted text is unchanged, and
+/// blank lines are dropped outside quotes.
+fn join_lines(text: &str) -> String {
+    let mut line = String::new();
+    for part in text.lines() {
+        if line.trim().is_empty() {
+            line = part.to_string();
+            continue;
+        }
+        let escapes = line.len() - line.trim_end_matches('\\').len();
+        if escapes % 2 == 1 {
+            line.pop();
+            line.push_str(part);
+            continue;
+        }
+        let parsed = parse(&line);
+        let quoted = matches!(parsed, Err(ParseError::UnterminatedQuote(_)));
+        if part.trim().is_empty() && !quoted {
+            continue;
+        }
+        let operator = matches!(
+            tokenize(&line).ok().and_then(|tokens| tokens.last().cloned()),
+            Some(Token::Pipe | Token::AndIf | Token::OrIf | Token::Amp | Token::Semi)
+        );
+        let separator = match parsed {
+            _ if quoted => "\n",
+            _ if operator => " ",
+            // Inside a compound command, `;` cannot follow words such as
+            // `then` or `do`.
+            Err(ParseError::UnexpectedEnd) => match parse(&format!("{}; {}", line, part)) {
+                Err(ParseError::UnexpectedToken(_)) => " ",
+                _ => "; ",
+            },
+            _ => "; ",
+        };
+        line.push_str(separator);
+        line.push_str(part);
+    }
+    line
+}
 
-            return child;
-            // return String::from(format!("<h2>cd to: {}</h2>", path));
+/// Edit the input line in `$VISUAL` or `$EDITOR` (`vi` by default), with
+/// the terminal given back to it meanwhile. The lines of the file are
+/// joined back into one line by `join_lines`.
+fn edit_line<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<()> {
+    let editor = app
+        .var("VISUAL")
+        .or_else(|| app.var("EDITOR"))
+        .filter(|editor| !editor.is_empty())
+        .unwrap_or("vi")
+        .to_string();
+    let written = create_temp_file().and_then(|(path, mut file)| {
+        file.write_all(format!("{}\n", app.input).as_bytes())?;
+        Ok(path)
+    });
+    let path = match written {
+        Ok(path) => path,
+        Err(err) => {
+            app.output.push_str(&format!("<c>{}</c>\n", escape_markup(&format!("{}: {}", editor, err))));
+            return Ok(());
         }
-        // "ls" => {
-        //     let mut cmd = Command::new(cmd);
-        //     cmd.args(args);
+    };
 
-        //     let out = cmd.output().unwrap();
-        //     let output = String::from_utf8(out.stdout).unwrap();
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .env_clear()
+        .envs(app.exported_vars())
+        .status();
+    execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+    enable_raw_mode()?;
+    terminal.clear()?;
 
-        //     let mut result = String::new();
-        //     let splitted: Vec<String> = output.split("\n").map(|x| x.to_string()).collect();
-        //     for split in splitted {
-        //         if split.len() > 0 {
-        //             let mut dir = std::env::current_dir()
-        //                 .unwrap()
-        //                 .to_str()
-        //                 .unwrap()
-        //                 .to_string();
-        //             dir.push('/');
-        //             dir.push_str(&split);
+    match status.and_then(|_| std::fs::read_to_string(&path)) {
+        Ok(text) => {
+            app.set_input(join_lines(&text));
+            app.vi_clamp();
+        }
+        Err(err) => app.output.push_str(&format!("<c>{}</c>\n", escape_markup(&format!("{}: {}", editor, err)))),
+    }
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
 
-        //             if std::fs::metadata(&dir).is_ok() {
-        //                 let md = std::fs::metadata(&dir).unwrap();
-        //                 if md.is_dir() == true {
-        //                     result.push_str("<h2> ");
-        //                     result.push_str(&split);
-        //                     result.push_str("</h2>");
-        //                     result.push('\n');
-        //                 } else {
-        //                     result.push_str("<i> ");
-        //                     result.push_str(&split);
-        //                     result.push_str("</i>");
-        //                     result.push('\n');
-        //                 }
-        //             } else {
-        //                 return output;
-        //             }
-        //         }
-        //     }
-        //     return result;
-        // }
-        _ => {
-            let args = splitted;
-            let child: Child = match Command::new(cmd).args(args).stdout(Stdio::piped()).spawn()
-            {
-                Ok(child) => child,
-                Err(_) => {
-                    panic!("failed to execute process");
-                }
-            };
+/// The name of a key press as written in the key bindings file, such as
+/// `Ctrl-R`, `Shift-Tab` or `a`.
+fn key_name(key: KeyEvent) -> Option<String> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+    let name = match key.code {
+        // The terminal reports Ctrl-\ to Ctrl-_ as Ctrl-4 to Ctrl-7.
+        KeyCode::Char(c @ '4'..='7') if ctrl => {
+            ["\\", "]", "^", "_"][c as usize - '4' as usize].to_string()
+        }
+        KeyCode::Char(' ') => String::from("Space"),
+        KeyCode::Char(c) if ctrl || alt => c.to_uppercase().to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::BackTab => return Some(String::from("Shift-Tab")),
+        KeyCode::Enter => String::from("Enter"),
+        KeyCode::Esc => String::from("Esc"),
+        KeyCode::Tab => String::from("Tab"),
+        KeyCode::Backspace => String::from("Backspace"),
+        KeyCode::Delete => String::from("Delete"),
+        KeyCode::Insert => String::from("Insert"),
+        KeyCode::Up => String::from("Up"),
+        KeyCode::Down => String::from("Down"),
+        KeyCode::Left => String::from("Left"),
+        KeyCode::Right => String::from("Right"),
+        KeyCode::Home => String::from("Home"),
+        KeyCode::End => String::from("End"),
+        KeyCode::PageUp => String::from("PageUp"),
+        KeyCode::PageDown => String::from("PageDown"),
+        KeyCode::F(n) => format!("F{}", n),
+        _ => return None,
+    };
+    let modifiers = match (ctrl, alt) {
+        (true, true) => "Ctrl-Alt-",
+        (true, false) => "Ctrl-",
+        (false, true) => "Alt-",
+        (false, false) => "",
+    };
+    Some(format!("{}{}", modifiers, name))
+}
 
-            return child;
+/// Launch the input line, or handle `c` and `help` which only change the
+/// display.
+fn run_input(app: &mut App) {
+    app.input_mode = InputMode::Editing;
+    let mut command: String = app.take_input();
+    app.output.clear();
+    app.output_job = None;
+    match history::expand_history(&command, &app.history) {
+        Ok(Some(expanded)) => {
+            app.output.push_str(&format!("<i>{}</i>\n", escape_markup(&expanded)));
+            command = expanded;
+        }
+        Ok(None) => {}
+        Err(err) => {
+            app.output.push_str(&format!("<c>{}</c>\n", escape_markup(&err.to_string())));
+            return;
         }
     }
+    app.add_history(&command);
+    if command == "c" {
+        app.output = "".to_string();
+        save_history(app, 0);
+    } else if command == "help" {
+        app.input_mode = InputMode::Helper;
+        save_history(app, 0);
+    } else {
+        app.command = command;
+        app.input_mode = InputMode::Output;
+        app.interrupted = false;
+    }
 }
 
-async fn read_output<B: Backend>(child: &mut Child, app: &mut App, terminal: &mut Terminal<B>) {
-    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+/// Do what a key is bound to. Returns false to quit.
+pub fn run_action(app: &mut App, action: Action) -> bool {
+    match action {
+        Action::EnterInsert => app.input_mode = InputMode::Editing,
+        Action::Quit => return false,
+        Action::ToggleStderr => app.show_stderr = !app.show_stderr,
+        Action::NextOutputJob => next_output_job(app),
+        Action::ShowDirs => {
+            app.dirs_index = 0;
+            app.input_mode = InputMode::Dirs;
+        }
+        Action::ViCancel => app.vi_reset(),
+        Action::ExitInsert => {
+            if app.keymap == Keymap::Vi {
+                app.vi_reset();
+                app.move_left();
+            }
+            app.input_mode = InputMode::Normal;
+        }
+        Action::Run => run_input(app),
+        Action::Complete => {
+            start_completion(app);
+            app.input_mode = InputMode::Completion;
+        }
+        Action::BrowseHistory => {
+            app.history_index = 0;
+            app.input_mode = InputMode::History;
+        }
+        Action::SearchHistory => {
+            app.search_query.clear();
+            app.search_history();
+            app.input_mode = InputMode::Search;
+        }
+        Action::MoveLeft => app.move_left(),
+        Action::MoveRight => app.move_right(),
+        Action::MoveHome => app.move_home(),
+        Action::MoveEnd => app.move_end(),
+        Action::MoveWordBackward => app.move_word_backward(),
+        Action::MoveWordForward => app.move_word_forward(),
+        Action::DeleteBackward => {
+            if let InputMode::Search = app.input_mode {
+                app.search_query.pop();
+                app.search_history();
+            } else {
+                app.delete_backward();
+            }
+        }
+        Action::DeleteForward => app.delete_forward(),
+        Action::KillWordBackward => app.kill_word_backward(),
+        Action::KillToStart => app.kill_to_start(),
+        Action::KillToEnd => app.kill_to_end(),
+        Action::Yank => app.yank(),
+        Action::YankPop => app.yank_pop(),
+        Action::Undo => app.undo(),
+        Action::Redo => app.redo(),
+        Action::OpenEditor => app.open_editor = true,
+        Action::AcceptCompletion => {
+            app.input_mode = InputMode::Editing;
+            if let Some(comp) = app.completion.get(app.completion_index) {
+                // The text after the cursor was kept at the end of each completion.
+                let rest = app.input.len() - app.editor.cursor;
+                app.set_input(comp.clone());
+                app.editor.cursor = app.input.len() - rest;
+            }
+        }
+        Action::CancelCompletion => app.input_mode = InputMode::Editing,
+        Action::NextCompletion => {
+            if !app.completion.is_empty() {
+                app.completion_index = (app.completion_index + 1) % app.completion.len();
+            }
+        }
+        Action::PreviousCompletion => {
+            if !app.completion.is_empty() {
+                app.completion_index =
+                    (app.completion_index + app.completion.len() - 1) % app.completion.len();
+            }
+        }
+        Action::CompletionDown => {
+            let columns = menu_columns(app);
+            if app.completion_index + columns < app.completion.len() {
+                app.completion_index += columns;
+            }
+        }
+        Action::CompletionUp => {
+            let columns = menu_columns(app);
+            if app.completion_index >= columns {
+                app.completion_index -= columns;
+            }
+        }
+        Action::AcceptHistory => {
+            app.input_mode = InputMode::Editing;
+            if let Some(entry) = app.history.get(app.history_index) {
+                app.set_input(entry.command.clone());
+            }
+        }
+        Action::CancelHistory => app.input_mode = InputMode::Editing,
+        Action::HistoryNext => {
+            app.history_index += 1;
+            if app.history_index >= app.history.len() {
+                app.history_index = 0;
+            }
+        }
+        Action::AcceptMatch => {
+            if let Some(result) = app.search_results.get(app.search_index) {
+                app.set_input(app.history[result.entry].command.clone());
+            }
+            app.input_mode = InputMode::Editing;
+        }
+        Action::CancelSearch => app.input_mode = InputMode::Editing,
+        Action::NextMatch => {
+            if app.search_index + 1 < app.search_results.len() {
+                app.search_index += 1;
+            }
+        }
+        Action::PreviousMatch => app.search_index = app.search_index.saturating_sub(1),
+        Action::NextPage => {
+            let last = app.search_results.len().saturating_sub(1);
+            app.search_index = (app.search_index + 10).min(last);
+        }
+        Action::PreviousPage => app.search_index = app.search_index.saturating_sub(10),
+        Action::JumpToDir => {
+            app.output.clear();
+            app.output_job = None;
+            builtins::rotate_dirs(app.dirs_index, app);
+            app.input_mode = InputMode::Editing;
+        }
+        Action::CloseDirs => app.input_mode = InputMode::Normal,
+        Action::NextDir => app.dirs_index = (app.dirs_index + 1) % app.dirs().len(),
+        Action::PreviousDir => {
+            let len = app.dirs().len();
+            app.dirs_index = (app.dirs_index + len - 1) % len;
+        }
+        Action::CloseHelper => app.input_mode = InputMode::Editing,
+        Action::Detach => detach_foreground(app),
+        Action::Interrupt => {
+            app.interrupted = true;
+            signal_foreground(app, libc::SIGINT);
+        }
+        Action::StopJob => signal_foreground(app, libc::SIGTSTP),
+        Action::QuitJob => signal_foreground(app, libc::SIGQUIT),
+    }
+    if action.edits_line() {
+        match app.input_mode {
+            InputMode::Completion => update_completion(app),
+            InputMode::Normal if app.keymap == Keymap::Vi => app.vi_clamp(),
+            _ => {}
+        }
+    }
+    true
+}
 
-    while let Some(line) = lines.next().await {
-        app.output.push_str(&line.unwrap());
-        app.output.push('\n');
-        terminal.draw(|f| ui(f, &app));
+/// Apply a key press to the current input mode, through the key bindings
+/// of the mode. Returns false to quit.
+pub fn handle_key(app: &mut App, key: KeyEvent) -> bool {
+    let typed = match key.code {
+        KeyCode::Char(c)
+            if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+        {
+            Some(c)
+        }
+        _ => None,
+    };
+    let mode = app.binding_mode();
+    // In the command mode of vi, the shell commands have the keys vi does
+    // not use.
+    if let (Some(c), "vi") = (typed, mode) {
+        match app.vi_key(c) {
+            ViAction::Insert => {
+                app.input_mode = InputMode::Editing;
+                return true;
+            }
+            ViAction::Editor => {
+                app.open_editor = true;
+                return true;
+            }
+            ViAction::None => return true,
+            ViAction::Unknown => {}
+        }
+    }
+    if let Some(action) = key_name(key).and_then(|name| app.bindings.action(mode, &name)) {
+        return run_action(app, action);
+    }
+    match (typed, &app.input_mode) {
+        (Some(c), InputMode::Editing) => app.insert_char(c),
+        (Some(c), InputMode::Completion) => {
+            app.insert_char(c);
+            update_completion(app);
+        }
+        (Some(c), InputMode::Search) => {
+            app.search_query.push(c);
+            app.search_history();
+        }
+        _ => {}
     }
+    true
 }
 
 pub async fn events<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<bool> {
-    if poll(Duration::from_millis(100))? {
-        if let Ok(Event::Key(key)) = event::read() {
-            match app.input_mode {
-                InputMode::Normal => match key.code {
-                    KeyCode::Char('i') => {
-                        app.input_mode = InputMode::Editing;
-                    }
-                    KeyCode::Char('q') => {
-                        return Ok(false);
-                    }
-                    _ => {}
-                },
-                InputMode::Editing => match key.code {
-                    KeyCode::Enter => {
-                        let command: String = app.input.drain(..).collect();
-                        app.history.push(command.clone());
-                        app.output.clear();
-                        if command == "c" {
-                            app.output = "".to_string();
-                        } else if command == "help" {
-                            app.input_mode = InputMode::Helper;
-                        } else {
-                            // app.output = run_command(command, app);
-                            app.command = command;
-                            app.input_mode = InputMode::Output;
-                        }
-                    }
-                    KeyCode::Char(c) => {
-                        app.input.push(c);
-                    }
-                    KeyCode::Backspace => {
-                        app.input.pop();
-                    }
-                    KeyCode::Esc => {
-                        app.input_mode = InputMode::Normal;
-                    }
-                    KeyCode::Tab => {
-                        app.completion.clear();
-                        app.completion_display.clear();
-                        app.completion_index = 0;
-                        create_completion(app);
-                        app.input_mode = InputMode::Completion;
-                    }
-                    KeyCode::Down => {
-                        app.history_index = 0;
-                        app.input_mode = InputMode::History;
-                    }
-                    _ => {}
-                },
-                InputMode::Completion => match key.code {
-                    KeyCode::Enter => {
-                        app.input_mode = InputMode::Editing;
-                        let comp: String = app.completion[app.completion_index].clone();
-                        app.input = comp;
-                    }
-                    KeyCode::Esc => {
-                        app.input_mode = InputMode::Editing;
-                    }
-                    KeyCode::Tab => {
-                        app.completion_index += 1;
-                        if app.completion.is_empty() == true {
-                            app.completion_index = 0;
-                        } else if app.completion_index > app.completion.len() - 1 {
-                            app.completion_index = 0;
-                        }
-                    }
-                    _ => {}
-                },
-                InputMode::History => match key.code {
-                    KeyCode::Enter => {
-                        app.input_mode = InputMode::Editing;
-                        let hist: String = app.history[app.history_index].clone();
-                        app.input = hist;
-                    }
-                    KeyCode::Esc => {
-                        app.input_mode = InputMode::Editing;
-                    }
-                    KeyCode::Tab => {
-                        app.history_index += 1;
-                        if app.history.is_empty() == true {
-                            app.history_index = 0;
-                        } else if app.history_index > app.history.len() - 1 {
-                            app.history_index = 0;
-                        }
-                    }
-                    _ => {}
-                },
-                InputMode::Helper => match key.code {
-                    KeyCode::Esc => {
-                        app.input_mode = InputMode::Editing;
-                    }
-                    _ => {}
-                },
-                InputMode::Output => match key.code {
-                    KeyCode::Esc => {
-                        app.input_mode = InputMode::Editing;
-                    }
-                    _ => {}
-                },
+    dispatch(app, terminal, Duration::from_millis(100))?;
+    if app.quit {
+        return Ok(false);
+    }
+    match app.input_mode {
+        InputMode::Output => {
+            match parse_aliased(&app.command, &app.aliases) {
+                Ok(list) => {
+                    run_list(&list, app, terminal).await;
+                }
+                Err(err) => {
+                    app.output.push_str(&format!("<c>{}</c>\n", escape_markup(&err.to_string())));
+                    // Syntax errors fail with 2, as in other shells.
+                    app.status = Some(CommandStatus {
+                        code: 2,
+                        signal: None,
+                        duration: Duration::ZERO,
+                    });
+                }
             }
+            save_history(app, app.last_status());
+            app.input_mode = InputMode::Editing;
             terminal.draw(|f| ui(f, &app))?;
         }
-    } else {
-        match app.input_mode {
-            InputMode::Output => {
-                let command = &app.command;
-                let mut child = run_command(command.to_string(), app);
-                read_output(&mut child, app, terminal).await;
-                app.input_mode = InputMode::Editing;
-                terminal.draw(|f| ui(f, &app))?;
+        _ => {}
+    }
+
+    return Ok(!app.quit);
+}
+
+/// Handle what the running pipelines sent, then wait up to `timeout` for a
+/// key and apply it, redrawing when anything changed. The main loop goes
+/// through it, and so does a command line waiting for its foreground jobs.
+pub fn dispatch<B: Backend>(
+    app: &mut App,
+    terminal: &mut Terminal<B>,
+    timeout: Duration,
+) -> Result<()> {
+    // A subshell has no terminal: it only writes what it printed.
+    if app.subshell.is_some() {
+        drain_events(app);
+        update_subshell(app);
+        std::thread::sleep(timeout);
+        return Ok(());
+    }
+    if drain_events(app) {
+        terminal.draw(|f| ui(f, &app))?;
+    }
+    if poll(timeout)? {
+        if let Ok(Event::Key(key)) = event::read() {
+            if !handle_key(app, key) {
+                app.quit = true;
+                return Ok(());
             }
-            _ => {}
+            if std::mem::take(&mut app.open_editor) {
+                edit_line(app, terminal)?;
+            }
+            terminal.draw(|f| ui(f, &app))?;
         }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joined_lines() {
+        assert_eq!(join_lines("ls\n"), "ls");
+        assert_eq!(join_lines("\n  \nls -l\n\npwd\n"), "ls -l; pwd");
+        assert_eq!(join_lines("echo a \\\n  b\n"), "echo a   b");
+        assert_eq!(join_lines("ls |\n  wc -l\n"), "ls |   wc -l");
+        assert_eq!(join_lines("if true\nthen\n  echo a\nfi\n"), "if true; then   echo a; fi");
+    }
 
-    return Ok(true);
+    #[test]
+    fn quoted_lines_are_kept() {
+        assert_eq!(join_lines("echo '  a\n\n  b  '\n"), "echo '  a\n\n  b  '");
+        assert_eq!(join_lines("echo \"x\n\ty \"\n"), "echo \"x\n\ty \"");
+    }
 }
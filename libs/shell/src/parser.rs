@@ -0,0 +1,1444 @@
+use lib_app::is_var_name;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// A piece of a word, remembering how it was quoted in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    /// Unquoted text.
+    Bare(String),
+    /// Text protected by single quotes or a backslash.
+    Quoted(String),
+    /// Text inside double quotes.
+    DoubleQuoted(String),
+    /// A parameter such as `$HOME`, `$?` or `${NAME:-default}`. Its value is
+    /// split into fields unless it appeared inside double quotes.
+    Param {
+        name: String,
+        default: Option<Vec<WordPart>>,
+        quoted: bool,
+    },
+}
+
+impl WordPart {
+    fn source(&self) -> String {
+        match self {
+            WordPart::Bare(s) | WordPart::Quoted(s) | WordPart::DoubleQuoted(s) => s.clone(),
+            WordPart::Param {
+                name,
+                default: None,
+                ..
+            } => format!("${}", name),
+            WordPart::Param {
+                name,
+                default: Some(default),
+                ..
+            } => {
+                let default: String = default.iter().map(|p| p.source()).collect();
+                format!("${{{}:-{}}}", name, default)
+            }
+        }
+    }
+}
+
+/// A shell word with its byte span in the source line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Word {
+    /// The word with quotes and escapes removed, and parameters left as
+    /// written.
+    pub fn text(&self) -> String {
+        self.parts.iter().map(|part| part.source()).collect()
+    }
+
+    /// True if any part of the word was quoted or escaped.
+    pub fn is_quoted(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|p| matches!(p, WordPart::Quoted(_) | WordPart::DoubleQuoted(_)))
+    }
+}
+
+/// A redirection operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOp {
+    /// `<`
+    Read,
+    /// `>`
+    Write,
+    /// `>>`
+    Append,
+    /// `<<<`
+    HereString,
+    /// `>&`
+    DupOut,
+    /// `<&`
+    DupIn,
+    /// `&>`
+    WriteAll,
+    /// `&>>`
+    AppendAll,
+}
+
+impl RedirectOp {
+    /// The descriptor redirected when none is written before the operator.
+    pub fn default_fd(&self) -> u32 {
+        match self {
+            RedirectOp::Read | RedirectOp::HereString | RedirectOp::DupIn => 0,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for RedirectOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            RedirectOp::Read => "<",
+            RedirectOp::Write => ">",
+            RedirectOp::Append => ">>",
+            RedirectOp::HereString => "<<<",
+            RedirectOp::DupOut => ">&",
+            RedirectOp::DupIn => "<&",
+            RedirectOp::WriteAll => "&>",
+            RedirectOp::AppendAll => "&>>",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    /// A redirection operator with the descriptor written before it, if any.
+    Redirect(Option<u32>, RedirectOp),
+    Pipe,
+    Semi,
+    Amp,
+    /// `&&`
+    AndIf,
+    /// `||`
+    OrIf,
+    /// `;;`, ending a `case` item.
+    DSemi,
+    LParen,
+    RParen,
+    Newline,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word.text()),
+            Token::Redirect(_, op) => write!(f, "{}", op),
+            Token::Pipe => write!(f, "|"),
+            Token::Semi => write!(f, ";"),
+            Token::Amp => write!(f, "&"),
+            Token::AndIf => write!(f, "&&"),
+            Token::OrIf => write!(f, "||"),
+            Token::DSemi => write!(f, ";;"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Newline => write!(f, "newline"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnterminatedQuote(char),
+    BadSubstitution(String),
+    EventNotFound(String),
+    SubstitutionFailed(String),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    /// Valid syntax that the shell cannot run, such as subshells.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnterminatedQuote(q) => {
+                write!(f, "syntax error: unterminated {} quote", quote_name(*q))
+            }
+            ParseError::BadSubstitution(text) => write!(f, "{}: bad substitution", text),
+            ParseError::EventNotFound(event) => write!(f, "{}: event not found", event),
+            ParseError::SubstitutionFailed(text) => write!(f, "{}: substitution failed", text),
+            ParseError::UnexpectedToken(tok) => {
+                write!(f, "syntax error near unexpected token `{}'", tok)
+            }
+            ParseError::UnexpectedEnd => write!(f, "syntax error: unexpected end of input"),
+            ParseError::Unsupported(what) => write!(f, "{}: not supported", what),
+        }
+    }
+}
+
+fn quote_name(q: char) -> &'static str {
+    match q {
+        '\'' => "single",
+        _ => "double",
+    }
+}
+
+/// A redirection such as `2>> log` or `2>&1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: u32,
+    pub op: RedirectOp,
+    pub target: Word,
+}
+
+/// A `NAME=value` word written before the command name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+impl Assignment {
+    /// Split `word` into an assignment if it starts with `NAME=`.
+    pub fn from_word(word: &Word) -> Option<Assignment> {
+        let first = match word.parts.first() {
+            Some(WordPart::Bare(first)) => first,
+            _ => return None,
+        };
+        let (name, value) = first.split_once('=')?;
+        if !is_var_name(name) {
+            return None;
+        }
+
+        let mut parts = word.parts[1..].to_vec();
+        if !value.is_empty() {
+            parts.insert(0, WordPart::Bare(value.to_string()));
+        }
+        Some(Assignment {
+            name: name.to_string(),
+            value: Word {
+                parts,
+                start: word.start + name.len() + 1,
+                end: word.end,
+            },
+        })
+    }
+}
+
+/// A command name followed by its arguments and redirections, with the
+/// variables it sets in its environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+impl SimpleCommand {
+    pub fn name(&self) -> Option<String> {
+        self.words.first().map(|w| w.text())
+    }
+
+    pub fn args(&self) -> Vec<String> {
+        self.words.iter().skip(1).map(|w| w.text()).collect()
+    }
+}
+
+/// Commands joined by `|`, run in the background when followed by `&`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+    pub background: bool,
+    /// The source text of the pipeline, as shown by `jobs`.
+    pub text: String,
+}
+
+/// An item of a `case` command: the patterns of `a|b)` and the list run
+/// when one of them matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+/// A pipeline or a compound command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Pipeline(Pipeline),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while list; do list; done`, or `until` when `until` is set.
+    Loop {
+        until: bool,
+        condition: List,
+        body: List,
+    },
+    /// `for name [in word...]; do list; done`, over the positional
+    /// parameters when there is no `in`.
+    For {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: List,
+    },
+    /// `case word in pattern) list;; ... esac`
+    Case {
+        word: Word,
+        arms: Vec<CaseArm>,
+    },
+    /// `{ list; }`
+    Group(List),
+    /// `name() compound-command`, with the source of the body, parsed again
+    /// at each call.
+    Function {
+        name: String,
+        body: String,
+    },
+}
+
+/// How two commands of an and-or list are joined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    /// `&&`: run the next command if the previous one succeeded.
+    And,
+    /// `||`: run the next command if the previous one failed.
+    Or,
+}
+
+/// Commands joined by `&&` and `||`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOr {
+    pub first: Command,
+    pub rest: Vec<(Connector, Command)>,
+}
+
+/// And-or lists separated by `;`, `&` or newlines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+/// The words that open or close compound commands where a command name
+/// is expected.
+const KEYWORDS: [&str; 16] = [
+    "if", "then", "elif", "else", "fi", "while", "until", "do", "done", "for", "in", "case",
+    "esac", "{", "}", "function",
+];
+
+/// The keywords after which a command name is expected.
+const COMMAND_KEYWORDS: [&str; 8] = ["if", "then", "elif", "else", "while", "until", "do", "{"];
+
+/// The keyword `token` is, when it is an unquoted reserved word.
+fn keyword(token: Option<&Token>) -> Option<&str> {
+    match token {
+        Some(Token::Word(word)) => alias_name(word).filter(|name| KEYWORDS.contains(name)),
+        _ => None,
+    }
+}
+
+/// Whether `word` is a keyword after which a command name is expected,
+/// such as `then`.
+pub fn is_command_keyword(word: &Word) -> bool {
+    alias_name(word).is_some_and(|name| COMMAND_KEYWORDS.contains(&name))
+}
+
+/// The function a word can define: unquoted, and not an assignment.
+fn function_name(word: &Word) -> Option<&str> {
+    alias_name(word).filter(|name| !name.contains('='))
+}
+
+/// Whether `token` starts a compound command.
+fn starts_compound(token: Option<&Token>) -> bool {
+    token == Some(&Token::LParen)
+        || matches!(
+            keyword(token),
+            Some("if" | "while" | "until" | "for" | "case" | "{" | "function")
+        )
+}
+
+fn is_metachar(c: char) -> bool {
+    matches!(
+        c,
+        ' ' | '\t' | '\n' | '|' | ';' | '&' | '<' | '>' | '(' | ')'
+    )
+}
+
+fn next_if(chars: &mut Peekable<CharIndices>, expected: char) -> bool {
+    chars.next_if(|(_, c)| *c == expected).is_some()
+}
+
+fn read_redirect(chars: &mut Peekable<CharIndices>, fd: Option<u32>) -> Token {
+    let op = match chars.next() {
+        Some((_, '<')) => {
+            if next_if(chars, '&') {
+                RedirectOp::DupIn
+            } else if chars.clone().take(2).filter(|(_, c)| *c == '<').count() == 2 {
+                chars.nth(1);
+                RedirectOp::HereString
+            } else {
+                RedirectOp::Read
+            }
+        }
+        Some((_, '&')) => {
+            chars.next();
+            if next_if(chars, '>') {
+                RedirectOp::AppendAll
+            } else {
+                RedirectOp::WriteAll
+            }
+        }
+        _ => {
+            if next_if(chars, '&') {
+                RedirectOp::DupOut
+            } else if next_if(chars, '>') {
+                RedirectOp::Append
+            } else {
+                RedirectOp::Write
+            }
+        }
+    };
+    Token::Redirect(fd, op)
+}
+
+fn push_part(parts: &mut Vec<WordPart>, part: WordPart) {
+    match (parts.last_mut(), part) {
+        (Some(WordPart::Bare(last)), WordPart::Bare(s)) => last.push_str(&s),
+        (Some(WordPart::Quoted(last)), WordPart::Quoted(s)) => last.push_str(&s),
+        (_, part) => parts.push(part),
+    }
+}
+
+fn read_word(chars: &mut Peekable<CharIndices>, len: usize) -> Result<Word, ParseError> {
+    let start = chars.peek().map_or(len, |(pos, _)| *pos);
+    let parts = read_parts(chars, false)?;
+    let end = chars.peek().map_or(len, |(pos, _)| *pos);
+
+    Ok(Word { parts, start, end })
+}
+
+/// Read the parts of a word up to a metacharacter, or up to the closing
+/// brace of a `${NAME:-default}` when `braced`.
+fn read_parts(
+    chars: &mut Peekable<CharIndices>,
+    braced: bool,
+) -> Result<Vec<WordPart>, ParseError> {
+    let mut parts: Vec<WordPart> = Vec::new();
+
+    while let Some(&(_, c)) = chars.peek() {
+        if braced && c == '}' {
+            return Ok(parts);
+        }
+        if !braced && is_metachar(c) {
+            break;
+        }
+        chars.next();
+        match c {
+            '\'' => {
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => quoted.push(c),
+                        None => return Err(ParseError::UnterminatedQuote('\'')),
+                    }
+                }
+                parts.push(WordPart::Quoted(quoted));
+            }
+            '"' => read_double_quoted(chars, &mut parts)?,
+            '\\' => match chars.next() {
+                Some((_, '\n')) => {}
+                Some((_, c)) => push_part(&mut parts, WordPart::Quoted(c.to_string())),
+                None => push_part(&mut parts, WordPart::Bare(String::from("\\"))),
+            },
+            '$' => push_part(&mut parts, read_param(chars, false)?),
+            '`' => return Err(ParseError::Unsupported("command substitution")),
+            c => push_part(&mut parts, WordPart::Bare(c.to_string())),
+        }
+    }
+
+    match braced {
+        true => Err(ParseError::BadSubstitution(String::from("${"))),
+        false => Ok(parts),
+    }
+}
+
+fn read_double_quoted(
+    chars: &mut Peekable<CharIndices>,
+    parts: &mut Vec<WordPart>,
+) -> Result<(), ParseError> {
+    let before = parts.len();
+    let mut quoted = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '"')) => break,
+            Some((_, '\\')) => match chars.next() {
+                Some((_, '\n')) => {}
+                Some((_, c)) if matches!(c, '"' | '\\' | '$' | '`') => quoted.push(c),
+                Some((_, c)) => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                None => return Err(ParseError::UnterminatedQuote('"')),
+            },
+            Some((_, '$')) => match read_param(chars, true)? {
+                WordPart::Bare(dollar) => quoted.push_str(&dollar),
+                param => {
+                    if !quoted.is_empty() {
+                        parts.push(WordPart::DoubleQuoted(std::mem::take(&mut quoted)));
+                    }
+                    parts.push(param);
+                }
+            },
+            Some((_, '`')) => return Err(ParseError::Unsupported("command substitution")),
+            Some((_, c)) => quoted.push(c),
+            None => return Err(ParseError::UnterminatedQuote('"')),
+        }
+    }
+    // An empty pair of quotes still makes an (empty) word.
+    if !quoted.is_empty() || parts.len() == before {
+        parts.push(WordPart::DoubleQuoted(quoted));
+    }
+    Ok(())
+}
+
+/// Read the parameter following a `$`. A `$` that starts no parameter is
+/// returned as bare text.
+fn read_param(chars: &mut Peekable<CharIndices>, quoted: bool) -> Result<WordPart, ParseError> {
+    let param = |name: String, default| WordPart::Param {
+        name,
+        default,
+        quoted,
+    };
+
+    match chars.peek() {
+        Some(&(_, c)) if is_special_param(c) => {
+            chars.next();
+            Ok(param(c.to_string(), None))
+        }
+        Some(&(_, c)) if c.is_ascii_alphabetic() || c == '_' => {
+            let name = read_name(chars);
+            Ok(param(name, None))
+        }
+        Some((_, '{')) => {
+            chars.next();
+            let name = match chars.peek() {
+                Some(&(_, c)) if "?$#@*".contains(c) => {
+                    chars.next();
+                    c.to_string()
+                }
+                _ => read_name(chars),
+            };
+            let default = match chars.next() {
+                Some((_, '}')) if !name.is_empty() => None,
+                Some((_, ':')) if !name.is_empty() && next_if(chars, '-') => {
+                    let default = read_parts(chars, true)?;
+                    chars.next();
+                    Some(default)
+                }
+                _ => return Err(ParseError::BadSubstitution(format!("${{{}", name))),
+            };
+            Ok(param(name, default))
+        }
+        Some((_, '(')) => match chars.clone().nth(1) {
+            Some((_, '(')) => Err(ParseError::Unsupported("arithmetic expansion")),
+            _ => Err(ParseError::Unsupported("command substitution")),
+        },
+        _ => Ok(WordPart::Bare(String::from("$"))),
+    }
+}
+
+/// Whether `$c` is a parameter on its own: `$?`, `$$`, `$#`, `$@`, `$*`
+/// or a positional parameter such as `$1`.
+fn is_special_param(c: char) -> bool {
+    "?$#@*".contains(c) || c.is_ascii_digit()
+}
+
+fn read_name(chars: &mut Peekable<CharIndices>) -> String {
+    let mut name = String::new();
+    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    name
+}
+
+/// Split a command line into words and operators.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        match c {
+            ' ' | '\t' => {
+                chars.next();
+            }
+            '\n' => {
+                chars.next();
+                tokens.push(Token::Newline);
+            }
+            '#' => {
+                while chars.peek().is_some_and(|(_, c)| *c != '\n') {
+                    chars.next();
+                }
+            }
+            '|' => {
+                chars.next();
+                match next_if(&mut chars, '|') {
+                    true => tokens.push(Token::OrIf),
+                    false => tokens.push(Token::Pipe),
+                }
+            }
+            ';' => {
+                chars.next();
+                match next_if(&mut chars, ';') {
+                    true => tokens.push(Token::DSemi),
+                    false => tokens.push(Token::Semi),
+                }
+            }
+            '&' => {
+                if input[pos + 1..].starts_with('>') {
+                    tokens.push(read_redirect(&mut chars, None));
+                } else {
+                    chars.next();
+                    match next_if(&mut chars, '&') {
+                        true => tokens.push(Token::AndIf),
+                        false => tokens.push(Token::Amp),
+                    }
+                }
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '<' | '>' => {
+                tokens.push(read_redirect(&mut chars, None));
+            }
+            _ => {
+                let word = read_word(&mut chars, input.len())?;
+                // Digits glued to a redirection name the descriptor, as in `2>`.
+                let io_number = match (&word.parts[..], chars.peek()) {
+                    ([WordPart::Bare(digits)], Some(&(next, '<' | '>'))) if next == word.end => {
+                        digits.parse::<u32>().ok()
+                    }
+                    _ => None,
+                };
+                match io_number {
+                    Some(fd) => tokens.push(read_redirect(&mut chars, Some(fd))),
+                    None => tokens.push(Token::Word(word)),
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(tok) => ParseError::UnexpectedToken(tok.to_string()),
+            None => ParseError::UnexpectedEnd,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    fn is_keyword(&self, name: &str) -> bool {
+        keyword(self.peek()) == Some(name)
+    }
+
+    /// Skip the keyword `name`, which must come next.
+    fn expect(&mut self, name: &str) -> Result<(), ParseError> {
+        match self.is_keyword(name) {
+            true => {
+                self.pos += 1;
+                Ok(())
+            }
+            false => Err(self.unexpected()),
+        }
+    }
+
+    /// Whether the next token ends a list that stops at one of `ends`.
+    /// Lists inside compound commands also stop at `;;` and `)`.
+    fn at_end(&self, ends: &[&str]) -> bool {
+        match self.peek() {
+            Some(Token::DSemi) | Some(Token::RParen) => !ends.is_empty(),
+            token => keyword(token).is_some_and(|name| ends.contains(&name)),
+        }
+    }
+
+    /// A list up to one of the keywords `ends`, or to the end of the input
+    /// when `ends` is empty.
+    fn list(&mut self, ends: &[&str]) -> Result<List, ParseError> {
+        let mut items: Vec<AndOr> = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            if self.peek().is_none() {
+                match ends.is_empty() {
+                    true => break,
+                    false => return Err(ParseError::UnexpectedEnd),
+                }
+            }
+            if self.at_end(ends) {
+                break;
+            }
+            let mut and_or = self.and_or()?;
+            match self.peek() {
+                Some(Token::Amp) => {
+                    match &mut and_or {
+                        AndOr {
+                            first: Command::Pipeline(pipeline),
+                            rest,
+                        } if rest.is_empty() => pipeline.background = true,
+                        _ => return Err(ParseError::Unsupported("background lists")),
+                    }
+                    self.pos += 1;
+                }
+                Some(Token::Semi) | Some(Token::Newline) => self.pos += 1,
+                None => {}
+                Some(_) if self.at_end(ends) => {}
+                Some(_) => return Err(self.unexpected()),
+            }
+            items.push(and_or);
+        }
+
+        Ok(List { items })
+    }
+
+    /// A list that must run at least one command, as in the bodies of
+    /// compound commands.
+    fn body(&mut self, ends: &[&str]) -> Result<List, ParseError> {
+        let list = self.list(ends)?;
+        match list.items.is_empty() {
+            true => Err(self.unexpected()),
+            false => Ok(list),
+        }
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.command()?;
+        let mut rest: Vec<(Connector, Command)> = Vec::new();
+
+        loop {
+            let connector = match self.peek() {
+                Some(Token::AndIf) => Connector::And,
+                Some(Token::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((connector, self.command()?));
+        }
+
+        Ok(AndOr { first, rest })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        let defines_function = matches!(
+            &self.tokens[self.pos..],
+            [Token::Word(word), Token::LParen, ..] if function_name(word).is_some()
+        );
+        let command = match keyword(self.peek()) {
+            Some("if") => self.if_clause()?,
+            Some("while") | Some("until") => self.loop_clause()?,
+            Some("for") => self.for_clause()?,
+            Some("case") => self.case_clause()?,
+            Some("{") => self.group()?,
+            Some("function") => self.function()?,
+            Some(_) => return Err(self.unexpected()),
+            None if self.peek() == Some(&Token::LParen) => {
+                return Err(ParseError::Unsupported("subshells"))
+            }
+            None if defines_function => self.function()?,
+            None => return Ok(Command::Pipeline(self.pipeline()?)),
+        };
+        match self.peek() {
+            Some(Token::Pipe) => Err(ParseError::Unsupported("pipes with compound commands")),
+            Some(Token::Redirect(..)) => {
+                Err(ParseError::Unsupported("redirections of compound commands"))
+            }
+            _ => Ok(command),
+        }
+    }
+
+    fn if_clause(&mut self) -> Result<Command, ParseError> {
+        let mut branches: Vec<(List, List)> = Vec::new();
+        let mut otherwise = None;
+
+        self.pos += 1;
+        loop {
+            let condition = self.body(&["then"])?;
+            self.expect("then")?;
+            branches.push((condition, self.body(&["elif", "else", "fi"])?));
+            match keyword(self.peek()) {
+                Some("elif") => self.pos += 1,
+                Some("else") => {
+                    self.pos += 1;
+                    otherwise = Some(self.body(&["fi"])?);
+                    self.expect("fi")?;
+                    break;
+                }
+                _ => {
+                    self.expect("fi")?;
+                    break;
+                }
+            }
+        }
+
+        Ok(Command::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn loop_clause(&mut self) -> Result<Command, ParseError> {
+        let until = self.is_keyword("until");
+        self.pos += 1;
+        let condition = self.body(&["do"])?;
+        self.expect("do")?;
+        let body = self.body(&["done"])?;
+        self.expect("done")?;
+
+        Ok(Command::Loop {
+            until,
+            condition,
+            body,
+        })
+    }
+
+    fn for_clause(&mut self) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let name = match self.peek() {
+            Some(Token::Word(word)) => alias_name(word).filter(|name| is_var_name(name)),
+            _ => None,
+        };
+        let name = name.ok_or_else(|| self.unexpected())?.to_string();
+        self.pos += 1;
+
+        self.skip_newlines();
+        let words = match self.is_keyword("in") {
+            true => {
+                self.pos += 1;
+                let mut words: Vec<Word> = Vec::new();
+                loop {
+                    match self.peek() {
+                        Some(Token::Word(word)) => words.push(word.clone()),
+                        Some(Token::Semi) | Some(Token::Newline) => break,
+                        _ => return Err(self.unexpected()),
+                    }
+                    self.pos += 1;
+                }
+                self.pos += 1;
+                Some(words)
+            }
+            false => {
+                if self.peek() == Some(&Token::Semi) {
+                    self.pos += 1;
+                }
+                None
+            }
+        };
+        self.skip_newlines();
+        self.expect("do")?;
+        let body = self.body(&["done"])?;
+        self.expect("done")?;
+
+        Ok(Command::For { name, words, body })
+    }
+
+    fn case_clause(&mut self) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let word = match self.peek() {
+            Some(Token::Word(word)) => word.clone(),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        self.skip_newlines();
+        self.expect("in")?;
+
+        let mut arms: Vec<CaseArm> = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.is_keyword("esac") {
+                self.pos += 1;
+                break;
+            }
+            if self.peek() == Some(&Token::LParen) {
+                self.pos += 1;
+            }
+            let mut patterns: Vec<Word> = Vec::new();
+            loop {
+                match self.peek() {
+                    Some(Token::Word(word)) => patterns.push(word.clone()),
+                    _ => return Err(self.unexpected()),
+                }
+                self.pos += 1;
+                match self.peek() {
+                    Some(Token::Pipe) => self.pos += 1,
+                    Some(Token::RParen) => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => return Err(self.unexpected()),
+                }
+            }
+            let body = self.list(&["esac"])?;
+            arms.push(CaseArm { patterns, body });
+            match self.peek() {
+                Some(Token::DSemi) => self.pos += 1,
+                _ if self.is_keyword("esac") => {}
+                _ => return Err(self.unexpected()),
+            }
+        }
+
+        Ok(Command::Case { word, arms })
+    }
+
+    fn group(&mut self) -> Result<Command, ParseError> {
+        self.pos += 1;
+        let list = self.body(&["}"])?;
+        self.expect("}")?;
+        Ok(Command::Group(list))
+    }
+
+    /// `name() body` or `function name [()] body`, where the body is a
+    /// compound command.
+    fn function(&mut self) -> Result<Command, ParseError> {
+        if self.is_keyword("function") {
+            self.pos += 1;
+        }
+        let name = match self.peek() {
+            Some(Token::Word(word)) if keyword(self.peek()).is_none() => function_name(word),
+            _ => None,
+        };
+        let name = name.ok_or_else(|| self.unexpected())?.to_string();
+        self.pos += 1;
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            if self.peek() != Some(&Token::RParen) {
+                return Err(self.unexpected());
+            }
+            self.pos += 1;
+        }
+
+        self.skip_newlines();
+        let start = match self.peek() {
+            Some(Token::Word(word)) if starts_compound(self.peek()) => word.start,
+            _ => return Err(self.unexpected()),
+        };
+        self.command()?;
+        // Compound commands end with a keyword.
+        let end = match &self.tokens[self.pos - 1] {
+            Token::Word(word) => word.end,
+            _ => return Err(self.unexpected()),
+        };
+        let body = self.input.get(start..end).unwrap_or_default().to_string();
+
+        Ok(Command::Function { name, body })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut commands = vec![self.simple_command()?];
+
+        while self.peek() == Some(&Token::Pipe) {
+            self.pos += 1;
+            self.skip_newlines();
+            if starts_compound(self.peek()) {
+                return Err(ParseError::Unsupported("pipes with compound commands"));
+            }
+            commands.push(self.simple_command()?);
+        }
+
+        let spans = commands.iter().flat_map(|c| {
+            let words = c.assignments.iter().map(|a| &a.value).chain(c.words.iter());
+            words.chain(c.redirects.iter().map(|r| &r.target))
+        });
+        let names = commands.iter().flat_map(|c| c.assignments.iter());
+        let start = spans
+            .clone()
+            .map(|w| w.start)
+            .chain(names.map(|a| a.value.start - a.name.len() - 1))
+            .min()
+            .unwrap_or(0);
+        let end = spans.map(|w| w.end).max().unwrap_or(0);
+
+        Ok(Pipeline {
+            commands,
+            background: false,
+            text: self.input[start..end].to_string(),
+        })
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut assignments: Vec<Assignment> = Vec::new();
+        let mut words: Vec<Word> = Vec::new();
+        let mut redirects: Vec<Redirect> = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    match Assignment::from_word(word).filter(|_| words.is_empty()) {
+                        Some(assignment) => assignments.push(assignment),
+                        None => words.push(word.clone()),
+                    }
+                    self.pos += 1;
+                }
+                Some(&Token::Redirect(fd, op)) => {
+                    self.pos += 1;
+                    let target = match self.peek() {
+                        Some(Token::Word(word)) => word.clone(),
+                        _ => return Err(self.unexpected()),
+                    };
+                    self.pos += 1;
+                    redirects.push(Redirect {
+                        fd: fd.unwrap_or_else(|| op.default_fd()),
+                        op,
+                        target,
+                    });
+                }
+                _ => break,
+            }
+        }
+
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(SimpleCommand {
+            assignments,
+            words,
+            redirects,
+        })
+    }
+}
+
+/// The alias a word names when it can be one: written without quotes,
+/// escapes or parameters.
+fn alias_name(word: &Word) -> Option<&str> {
+    match &word.parts[..] {
+        [WordPart::Bare(name)] => Some(name),
+        _ => None,
+    }
+}
+
+/// Replace the aliases in command position, which includes the start of
+/// the bodies of compound commands, by the tokens of their value.
+/// A word coming from an alias is not expanded as that alias again, and
+/// the word after an alias whose value ends with a blank is expanded too.
+/// The new words keep the span of the alias they replace.
+fn expand_aliases(
+    tokens: Vec<Token>,
+    aliases: &BTreeMap<String, String>,
+) -> Result<Vec<Token>, ParseError> {
+    // Each token with the aliases it comes from, and whether it is checked
+    // for an alias because of the trailing blank rule.
+    let mut tokens: Vec<(Token, Vec<String>, bool)> = tokens
+        .into_iter()
+        .map(|token| (token, Vec::new(), false))
+        .collect();
+    let mut command_position = true;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let (token, expanded, after_blank) = &tokens[i];
+        let word = match token {
+            Token::Word(word) => word,
+            Token::Redirect(..) => {
+                // Skip the target.
+                i += 2;
+                continue;
+            }
+            _ => {
+                command_position = true;
+                i += 1;
+                continue;
+            }
+        };
+        let alias = alias_name(word)
+            .filter(|name| !expanded.iter().any(|done| done == name))
+            .and_then(|name| Some((name.to_string(), aliases.get(name)?)));
+        match alias {
+            Some((name, value)) if command_position || *after_blank => {
+                let (start, end) = (word.start, word.end);
+                let mut expanded = expanded.clone();
+                expanded.push(name);
+                let replacement: Vec<(Token, Vec<String>, bool)> = tokenize(value)?
+                    .into_iter()
+                    .enumerate()
+                    .map(|(n, mut token)| {
+                        if let Token::Word(word) = &mut token {
+                            word.start = start;
+                            word.end = end;
+                        }
+                        // The first word is checked again, as the alias was.
+                        (token, expanded.clone(), n == 0)
+                    })
+                    .collect();
+                let after = i + replacement.len();
+                tokens.splice(i..=i, replacement);
+                if value.ends_with(' ') || value.ends_with('\t') {
+                    if let Some(next) = tokens.get_mut(after) {
+                        next.2 = true;
+                    }
+                }
+            }
+            _ => {
+                let keeps_position =
+                    Assignment::from_word(word).is_some() || is_command_keyword(word);
+                command_position = command_position && keeps_position;
+                i += 1;
+            }
+        }
+    }
+
+    Ok(tokens.into_iter().map(|(token, _, _)| token).collect())
+}
+
+/// Parse a full command line into a list of pipelines.
+pub fn parse(input: &str) -> Result<List, ParseError> {
+    parse_aliased(input, &BTreeMap::new())
+}
+
+/// Parse a command line, expanding the `aliases` first.
+pub fn parse_aliased(input: &str, aliases: &BTreeMap<String, String>) -> Result<List, ParseError> {
+    let tokens = expand_aliases(tokenize(input)?, aliases)?;
+    Parser {
+        input,
+        tokens,
+        pos: 0,
+    }
+    .list(&[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(input: &str) -> Vec<String> {
+        tokenize(input)
+            .unwrap()
+            .iter()
+            .map(|token| token.to_string())
+            .collect()
+    }
+
+    fn command(input: &str) -> Command {
+        let mut list = parse(input).unwrap();
+        assert_eq!(list.items.len(), 1);
+        list.items.remove(0).first
+    }
+
+    fn pipeline(input: &str) -> Pipeline {
+        match command(input) {
+            Command::Pipeline(pipeline) => pipeline,
+            command => panic!("not a pipeline: {:?}", command),
+        }
+    }
+
+    fn texts(words: &[Word]) -> Vec<String> {
+        words.iter().map(|word| word.text()).collect()
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            words(r#"echo 'a b' "c $d" e\ f"#),
+            ["echo", "a b", "c $d", "e f"]
+        );
+        assert_eq!(words(r#"'it'"'"s"#), ["it's"]);
+        assert_eq!(words(r#"a"" '' """#), ["a", "", ""]);
+        assert_eq!(
+            tokenize("echo 'a"),
+            Err(ParseError::UnterminatedQuote('\''))
+        );
+        assert_eq!(
+            tokenize(r#"echo "a"#),
+            Err(ParseError::UnterminatedQuote('"'))
+        );
+
+        let word = &pipeline("echo 'x' y").commands[0].words;
+        assert!(word[1].is_quoted());
+        assert!(!word[2].is_quoted());
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(words(r"a\;b \$HOME"), ["a;b", "$HOME"]);
+        assert_eq!(words(r#""a\"b\\c\$d\x""#), [r#"a"b\c$d\x"#]);
+        assert_eq!(words("ab\\\ncd"), ["abcd"]);
+        assert_eq!(words("a\\"), ["a\\"]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(words("echo a # b c"), ["echo", "a"]);
+        assert_eq!(words("echo a#b"), ["echo", "a#b"]);
+        assert_eq!(words("# all\necho"), ["newline", "echo"]);
+        assert!(parse("# nothing").unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn parameters() {
+        let words = pipeline(r#"echo $HOME "$1" ${X:-a b} $? $"#).commands[0]
+            .words
+            .clone();
+        assert_eq!(
+            words[1].parts,
+            [WordPart::Param {
+                name: String::from("HOME"),
+                default: None,
+                quoted: false,
+            }]
+        );
+        assert_eq!(
+            words[2].parts,
+            [WordPart::Param {
+                name: String::from("1"),
+                default: None,
+                quoted: true,
+            }]
+        );
+        assert_eq!(words[3].text(), "${X:-a b}");
+        assert_eq!(words[4].text(), "$?");
+        assert_eq!(words[5].text(), "$");
+        assert_eq!(
+            tokenize("echo ${X"),
+            Err(ParseError::BadSubstitution(String::from("${X")))
+        );
+    }
+
+    #[test]
+    fn redirections() {
+        let command = &pipeline("cmd 2>&1 > out <<< 'some text' 2>> log &> all < in").commands[0];
+        let redirects: Vec<(u32, RedirectOp, String)> = command
+            .redirects
+            .iter()
+            .map(|r| (r.fd, r.op, r.target.text()))
+            .collect();
+        assert_eq!(
+            redirects,
+            [
+                (2, RedirectOp::DupOut, String::from("1")),
+                (1, RedirectOp::Write, String::from("out")),
+                (0, RedirectOp::HereString, String::from("some text")),
+                (2, RedirectOp::Append, String::from("log")),
+                (1, RedirectOp::WriteAll, String::from("all")),
+                (0, RedirectOp::Read, String::from("in")),
+            ]
+        );
+        assert_eq!(texts(&command.words), ["cmd"]);
+
+        // Only digits glued to the operator name a descriptor.
+        assert_eq!(pipeline("echo 2 >f").commands[0].words.len(), 2);
+        assert_eq!(pipeline("echo a2>f").commands[0].words.len(), 2);
+        assert_eq!(
+            pipeline("cat 0<&- x").commands[0].redirects[0].op,
+            RedirectOp::DupIn
+        );
+        assert_eq!(parse("echo >"), Err(ParseError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn assignments() {
+        let command = &pipeline("A=1 B='x y' env C=2").commands[0];
+        let names: Vec<&str> = command
+            .assignments
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect();
+        assert_eq!(names, ["A", "B"]);
+        assert_eq!(command.assignments[1].value.text(), "x y");
+        assert_eq!(texts(&command.words), ["env", "C=2"]);
+    }
+
+    #[test]
+    fn pipelines_and_lists() {
+        let pipeline = pipeline("ls -l | grep x |\n wc -l");
+        assert_eq!(pipeline.commands.len(), 3);
+        assert_eq!(pipeline.text, "ls -l | grep x |\n wc -l");
+
+        let list = parse("a && b || c; d\ne &").unwrap();
+        assert_eq!(list.items.len(), 3);
+        let connectors: Vec<Connector> = list.items[0].rest.iter().map(|(c, _)| *c).collect();
+        assert_eq!(connectors, [Connector::And, Connector::Or]);
+        assert!(list.items[1].rest.is_empty());
+        match &list.items[2].first {
+            Command::Pipeline(pipeline) => assert!(pipeline.background),
+            command => panic!("not a pipeline: {:?}", command),
+        }
+
+        assert_eq!(parse("a &&\n b").unwrap().items[0].rest.len(), 1);
+        assert_eq!(
+            parse("a && b &"),
+            Err(ParseError::Unsupported("background lists"))
+        );
+        assert_eq!(
+            parse("a | | b"),
+            Err(ParseError::UnexpectedToken(String::from("|")))
+        );
+        assert_eq!(parse("a &&"), Err(ParseError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn if_clause() {
+        match command("if a; then b; elif c\nthen d; else e; fi") {
+            Command::If {
+                branches,
+                otherwise,
+            } => {
+                assert_eq!(branches.len(), 2);
+                assert_eq!(otherwise.map(|list| list.items.len()), Some(1));
+            }
+            command => panic!("not an if: {:?}", command),
+        }
+        assert_eq!(parse("if a; then b"), Err(ParseError::UnexpectedEnd));
+        assert_eq!(
+            parse("if a; then fi"),
+            Err(ParseError::UnexpectedToken(String::from("fi")))
+        );
+        // Keywords are only recognized unquoted, in command position.
+        assert_eq!(
+            texts(&pipeline("echo if then 'fi'").commands[0].words).len(),
+            4
+        );
+    }
+
+    #[test]
+    fn loops() {
+        match command("until a; do b; c; done") {
+            Command::Loop { until, body, .. } => {
+                assert!(until);
+                assert_eq!(body.items.len(), 2);
+            }
+            command => panic!("not a loop: {:?}", command),
+        }
+        match command("for i in 1 \"2 3\"; do echo $i; done") {
+            Command::For { name, words, .. } => {
+                assert_eq!(name, "i");
+                assert_eq!(texts(&words.unwrap()), ["1", "2 3"]);
+            }
+            command => panic!("not a for: {:?}", command),
+        }
+        match command("for arg\ndo echo; done") {
+            Command::For { words, .. } => assert_eq!(words, None),
+            command => panic!("not a for: {:?}", command),
+        }
+        assert_eq!(
+            parse("for 1x in a; do b; done"),
+            Err(ParseError::UnexpectedToken(String::from("1x")))
+        );
+    }
+
+    #[test]
+    fn case_clause() {
+        match command("case $x in\n a|b) one;;\n (*) two; three\nesac") {
+            Command::Case { word, arms } => {
+                assert_eq!(word.text(), "$x");
+                assert_eq!(texts(&arms[0].patterns), ["a", "b"]);
+                assert_eq!(texts(&arms[1].patterns), ["*"]);
+                assert_eq!(arms[1].body.items.len(), 2);
+            }
+            command => panic!("not a case: {:?}", command),
+        }
+        assert!(parse("case x in esac").is_ok());
+    }
+
+    #[test]
+    fn groups_and_functions() {
+        match command("{ a; b; }") {
+            Command::Group(list) => assert_eq!(list.items.len(), 2),
+            command => panic!("not a group: {:?}", command),
+        }
+        for input in [
+            "f() { echo hi; }",
+            "function f { echo hi; }",
+            "function f()\n{ echo hi; }",
+        ] {
+            assert_eq!(
+                command(input),
+                Command::Function {
+                    name: String::from("f"),
+                    body: String::from("{ echo hi; }"),
+                }
+            );
+        }
+        assert_eq!(
+            parse("f() echo"),
+            Err(ParseError::UnexpectedToken(String::from("echo")))
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        for (input, what) in [
+            ("echo `date`", "command substitution"),
+            ("echo \"`date`\"", "command substitution"),
+            ("echo $(date)", "command substitution"),
+            ("echo \"$(date)\"", "command substitution"),
+            ("echo $((1 + 1))", "arithmetic expansion"),
+            ("(ls)", "subshells"),
+            ("{ a; } | b", "pipes with compound commands"),
+            ("a | if b; then c; fi", "pipes with compound commands"),
+            ("{ a; } > f", "redirections of compound commands"),
+        ] {
+            assert_eq!(
+                parse(input),
+                Err(ParseError::Unsupported(what)),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(words(r#""\`date\`""#), ["`date`"]);
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases = BTreeMap::from([
+            (String::from("ll"), String::from("ls -l")),
+            (String::from("ls"), String::from("ls -F")),
+            (String::from("sudo"), String::from("sudo ")),
+        ]);
+        let list = parse_aliased("ll x; sudo ll; 'll'; echo ll", &aliases).unwrap();
+        let commands: Vec<Vec<String>> = list
+            .items
+            .iter()
+            .map(|item| match &item.first {
+                Command::Pipeline(pipeline) => texts(&pipeline.commands[0].words),
+                command => panic!("not a pipeline: {:?}", command),
+            })
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                vec!["ls", "-F", "-l", "x"],
+                vec!["sudo", "ls", "-F", "-l"],
+                vec!["ll"],
+                vec!["echo", "ll"],
+            ]
+        );
+    }
+}
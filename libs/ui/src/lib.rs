@@ -3,50 +3,129 @@ use tui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, Clear, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
     Frame,
 };
 
-use unicode_width::UnicodeWidthStr;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use std::time::Duration;
 
 use lib_app::*;
 
-fn display_completion<B: Backend>(f: &mut Frame<B>, app: &App, chunk: Rect) {
-    let mut completion_string: Vec<Span> = Vec::new();
-    let mut index = 0;
-    let mut i = 0;
-    for comp in app.completion_display.iter() {
-        if i < app.completion_index {
-            index += comp.len() + 1;
+/// The width of the completion `i`: its name, then two spaces and its
+/// description when it has one.
+fn completion_width(app: &App, i: usize) -> usize {
+    let name = app.completion_display[i].width();
+    match app.completion_descriptions.get(i) {
+        Some(description) if !description.is_empty() => name + 2 + description.width(),
+        _ => name,
+    }
+}
+
+/// The number of columns of the completion menu when it is `width` cells
+/// wide. The few completions wider than nine out of ten others do not
+/// count, and get cut.
+pub fn completion_columns(app: &App, width: u16) -> usize {
+    let mut widths: Vec<usize> = (0..app.completion_display.len())
+        .map(|i| completion_width(app, i))
+        .collect();
+    widths.sort();
+    let wide = widths.get(widths.len() * 9 / 10).copied().unwrap_or(0);
+    let inner = (width as usize).saturating_sub(2);
+    (inner / (wide + 2)).max(1)
+}
+
+/// `text` cut down to `width` cells, ending with `…` when it was cut.
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut result = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
         }
-        if i == app.completion_index {
-            completion_string.push(Span::styled(comp, Style::default().fg(Color::Blue)));
-        } else {
-            completion_string.push(Span::raw(comp));
+        result.push(c);
+        used += w;
+    }
+    if width > 0 {
+        result.push('…');
+    }
+    result
+}
+
+/// The completion menu, drawn over `area` from its top: a grid of the
+/// completions and their descriptions, scrolled to the selected one.
+fn display_completion<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
+    let count = app.completion_display.len();
+    let columns = completion_columns(app, area.width);
+    let cell = (area.width as usize).saturating_sub(2) / columns;
+    let rows = count.div_ceil(columns);
+    let height = (rows.max(1) as u16 + 2).min(area.height);
+    let visible = height.saturating_sub(2) as usize;
+    let first = (app.completion_index / columns + 1).saturating_sub(visible);
+
+    let mut lines: Vec<Spans> = Vec::new();
+    for row in first..rows.min(first + visible) {
+        let mut spans: Vec<Span> = Vec::new();
+        for i in row * columns..count.min((row + 1) * columns) {
+            let selected = match i == app.completion_index {
+                true => Style::default().add_modifier(Modifier::REVERSED),
+                false => Style::default(),
+            };
+            let name = truncate(&app.completion_display[i], cell.saturating_sub(2));
+            let mut used = name.width();
+            spans.push(Span::styled(name, selected));
+
+            let room = cell.saturating_sub(used + 4);
+            match app.completion_descriptions.get(i) {
+                Some(description) if !description.is_empty() && room > 0 => {
+                    let description = format!("  {}", truncate(description, room));
+                    used += description.width();
+                    let style = selected.fg(color(app.config.theme.muted));
+                    spans.push(Span::styled(description, style));
+                }
+                _ => {}
+            }
+            spans.push(Span::raw(" ".repeat(cell.saturating_sub(used))));
         }
-        completion_string.push(Span::raw(" "));
-        i += 1;
+        lines.push(Spans::from(spans));
     }
-    let completion = Paragraph::new(Text::from(Spans::from(completion_string)))
-        .block(Block::default().borders(Borders::ALL).title("Completion"))
-        .style(Style::default().fg(Color::Yellow));
-    f.render_widget(completion, chunk);
 
-    f.set_cursor(chunk.x + index as u16 + 1, chunk.y + 1);
+    let title = match count {
+        0 => {
+            lines.push(Spans::from(Span::styled(
+                "no match",
+                Style::default().fg(color(app.config.theme.muted)),
+            )));
+            String::from("Completion")
+        }
+        _ => format!("Completion {}/{}", app.completion_index + 1, count),
+    };
+    let menu = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .style(Style::default().fg(color(app.config.theme.accent)));
+    let popup = Rect::new(area.x, area.y, area.width, height);
+    f.render_widget(Clear, popup);
+    f.render_widget(menu, popup);
 }
 
 fn display_history<B: Backend>(f: &mut Frame<B>, app: &App, chunk: Rect) {
     let mut history_string: Vec<Span> = Vec::new();
     let mut index = 0;
     let mut i = 0;
-    for hist in app.history.iter() {
+    for hist in app.history.iter().map(|entry| &entry.command) {
         if i < app.history_index {
             index += hist.len() + 3;
         }
         if i == app.history_index {
-            history_string.push(Span::styled("\"", Style::default().fg(Color::Blue)));
-            history_string.push(Span::styled(hist, Style::default().fg(Color::Blue)));
-            history_string.push(Span::styled("\"", Style::default().fg(Color::Blue)));
+            let selected = Style::default().fg(color(app.config.theme.highlight));
+            history_string.push(Span::styled("\"", selected));
+            history_string.push(Span::styled(hist, selected));
+            history_string.push(Span::styled("\"", selected));
         } else {
             history_string.push(Span::raw("\""));
             history_string.push(Span::raw(hist));
@@ -57,21 +136,90 @@ fn display_history<B: Backend>(f: &mut Frame<B>, app: &App, chunk: Rect) {
     }
     let history = Paragraph::new(Text::from(Spans::from(history_string)))
         .block(Block::default().borders(Borders::ALL).title("History"))
-        .style(Style::default().fg(Color::Yellow));
+        .style(Style::default().fg(color(app.config.theme.accent)));
     f.render_widget(history, chunk);
 
     f.set_cursor(chunk.x + index as u16 + 1, chunk.y + 1);
 }
 
+fn display_search<B: Backend>(f: &mut Frame<B>, app: &App, query_chunk: Rect, chunk: Rect) {
+    let query = Paragraph::new(app.search_query.as_ref())
+        .block(Block::default().borders(Borders::ALL).title("Search history"))
+        .style(Style::default().fg(color(app.config.theme.accent)));
+    f.render_widget(query, query_chunk);
+    f.set_cursor(
+        query_chunk.x + app.search_query.width() as u16 + 1,
+        query_chunk.y + 1,
+    );
+
+    let matched = Style::default()
+        .fg(color(app.config.theme.highlight))
+        .add_modifier(Modifier::BOLD);
+    let items: Vec<ListItem> = app
+        .search_results
+        .iter()
+        .map(|result| {
+            let entry = &app.history[result.entry];
+            let mut spans: Vec<Span> = entry
+                .command
+                .chars()
+                .enumerate()
+                .map(|(i, c)| match result.positions.contains(&i) {
+                    true => Span::styled(c.to_string(), matched),
+                    false => Span::raw(c.to_string()),
+                })
+                .collect();
+            if entry.status.is_some_and(|code| code != 0) {
+                let style = Style::default().fg(color(app.config.theme.error));
+                spans.push(Span::styled(" ✗", style));
+            }
+            spans.push(Span::styled(
+                format!("  {}", app.tilde(&entry.cwd)),
+                Style::default().fg(color(app.config.theme.muted)),
+            ));
+            ListItem::new(Spans::from(spans))
+        })
+        .collect();
+
+    let title = format!("History {}/{}", app.search_results.len(), app.history.len());
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+    let mut state = ListState::default();
+    if !app.search_results.is_empty() {
+        state.select(Some(app.search_index));
+    }
+    f.render_stateful_widget(list, chunk, &mut state);
+}
+
 fn display_output<B: Backend>(f: &mut Frame<B>, app: &App, chunk: Rect) {
-    let lines: Vec<String> = app.output.lines().map(|x| String::from(x)).collect();
+    let job = app.output_job.and_then(|number| app.job(number));
+    let output = match job {
+        Some(job) => &job.output,
+        None => &app.output,
+    };
+
+    let lines: Vec<String> = output
+        .lines()
+        .filter(|x| app.show_stderr || !x.starts_with("<e>"))
+        .map(|x| String::from(x))
+        .collect();
 
-    let text = construct_message(&lines);
+    let text = construct_message(&app.config.theme, &lines);
+
+    let mut title = String::from("Outputs");
+    if let Some(job) = job {
+        title.push_str(&format!(" [{}] {}", job.number, job.command));
+    }
+    if !app.show_stderr {
+        title.push_str(" (stderr hidden)");
+    }
 
     let output = Paragraph::new(text)
-        .block(Block::default().borders(Borders::ALL).title("Outputs"))
+        .block(Block::default().borders(Borders::ALL).title(title))
         .style(match app.input_mode {
-            InputMode::Output => Style::default().fg(Color::Yellow),
+            InputMode::Output => Style::default().fg(color(app.config.theme.accent)),
             _ => Style::default(),
         });
 
@@ -80,7 +228,7 @@ fn display_output<B: Backend>(f: &mut Frame<B>, app: &App, chunk: Rect) {
 
 fn create_popup<B: Backend>(
     f: &mut Frame<B>,
-    _app: &App,
+    app: &App,
     text: Vec<Spans>,
     size_x: u16,
     size_y: u16,
@@ -88,7 +236,7 @@ fn create_popup<B: Backend>(
 ) {
     let popup = Paragraph::new(text)
         .block(Block::default().borders(Borders::ALL).title(title))
-        .style(Style::default().fg(Color::Yellow));
+        .style(Style::default().fg(color(app.config.theme.accent)));
 
     let popup_layout_y = Layout::default()
         .direction(Direction::Vertical)
@@ -118,9 +266,33 @@ fn create_popup<B: Backend>(
     f.render_widget(popup, popup_layout);
 }
 
-fn contains_patterns(line: &String, patterns: &Vec<&str>) -> bool {
-    for pattern in patterns.iter() {
-        if line.contains(pattern) {
+fn display_dirs<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let lines: Vec<String> = app
+        .dirs()
+        .iter()
+        .enumerate()
+        .map(|(i, dir)| {
+            let line = format!("{:>2}  {}", i, app.tilde(dir));
+            if i == app.dirs_index {
+                format!("<h2>{}</h2>", line)
+            } else {
+                line
+            }
+        })
+        .collect();
+    let message = construct_message(&app.config.theme, &lines);
+    create_popup(f, app, message, 50, 40, "Directory stack");
+}
+
+fn find_patterned(line: &str, start_pattern: &str, end_pattern: &str) -> Option<(usize, usize)> {
+    let start = line.find(start_pattern)?;
+    let end = line[start..].find(end_pattern)? + start;
+    Some((start, end))
+}
+
+fn contains_patterns(line: &String, start_patterns: &Vec<&str>, end_patterns: &Vec<&str>) -> bool {
+    for (start, end) in start_patterns.iter().zip(end_patterns.iter()) {
+        if find_patterned(line, start, end).is_some() {
             return true;
         }
     }
@@ -128,10 +300,7 @@ fn contains_patterns(line: &String, patterns: &Vec<&str>) -> bool {
 }
 
 fn get_patterned(line: String, start_pattern: &str, end_pattern: &str) -> (String, String, String) {
-    if line.contains(start_pattern) {
-        let start = line.find(start_pattern).unwrap();
-        let end = line.find(end_pattern).unwrap();
-
+    if let Some((start, end)) = find_patterned(&line, start_pattern, end_pattern) {
         let before = String::from(&line[0..start]);
         let between = String::from(&line[(start + start_pattern.len())..end]);
         let after = String::from(&line[(end + end_pattern.len())..]);
@@ -141,41 +310,46 @@ fn get_patterned(line: String, start_pattern: &str, end_pattern: &str) -> (Strin
     return (String::from(""), String::from(""), String::from(line));
 }
 
-fn construct_line(line: String, text: &mut Vec<Span>) {
+fn construct_line(theme: &Theme, line: String, text: &mut Vec<Span>) {
     let mut rest = String::from(line);
 
-    let start_patterns = vec!["<h1>", "<h2>", "<c>", "<i>"];
-    let end_patterns = vec!["</h1>", "</h2>", "</c>", "</i>"];
+    let start_patterns = vec!["<h1>", "<h2>", "<c>", "<i>", "<e>"];
+    let end_patterns = vec!["</h1>", "</h2>", "</c>", "</i>", "</e>"];
 
-    while contains_patterns(&rest, &start_patterns) {
+    while contains_patterns(&rest, &start_patterns, &end_patterns) {
         for i in 0..start_patterns.len() {
             let (before, between, after) = get_patterned(rest, start_patterns[i], end_patterns[i]);
             if !before.is_empty() {
-                construct_line(before, text);
+                construct_line(theme, before, text);
             }
             if !between.is_empty() {
                 match i {
                     0 => {
                         let style = Style::default()
-                            .fg(Color::Green)
+                            .fg(color(theme.success))
                             .add_modifier(Modifier::UNDERLINED);
 
-                        text.push(Span::styled(between, style));
+                        text.push(Span::styled(unescape_markup(&between), style));
                     }
                     1 => {
-                        let style = Style::default().fg(Color::Blue);
+                        let style = Style::default().fg(color(theme.highlight));
 
-                        text.push(Span::styled(between, style));
+                        text.push(Span::styled(unescape_markup(&between), style));
                     }
                     2 => {
-                        let style = Style::default().fg(Color::Red);
+                        let style = Style::default().fg(color(theme.error));
 
-                        text.push(Span::styled(between, style));
+                        text.push(Span::styled(unescape_markup(&between), style));
                     }
                     3 => {
-                        let style = Style::default().fg(Color::Magenta);
+                        let style = Style::default().fg(color(theme.info));
+
+                        text.push(Span::styled(unescape_markup(&between), style));
+                    }
+                    4 => {
+                        let style = Style::default().fg(color(theme.stderr));
 
-                        text.push(Span::styled(between, style));
+                        text.push(Span::styled(unescape_markup(&between), style));
                     }
                     _ => {}
                 }
@@ -184,16 +358,16 @@ fn construct_line(line: String, text: &mut Vec<Span>) {
         }
     }
 
-    text.push(Span::raw(rest));
+    text.push(Span::raw(unescape_markup(&rest)));
 }
 
-fn construct_message(lines: &Vec<String>) -> Vec<Spans> {
+fn construct_message<'a>(theme: &Theme, lines: &'a Vec<String>) -> Vec<Spans<'a>> {
     let mut texts: Vec<Spans> = Vec::new();
 
     for line in lines.iter() {
         let mut text: Vec<Span> = Vec::new();
 
-        construct_line(line.to_string(), &mut text);
+        construct_line(theme, line.to_string(), &mut text);
 
         texts.push(Spans::from(text));
     }
@@ -201,6 +375,197 @@ fn construct_message(lines: &Vec<String>) -> Vec<Spans> {
     texts
 }
 
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+/// A mode, the introduction of its help line, and the actions whose keys
+/// it shows with what they do.
+type HelpRow = (&'static str, &'static str, &'static [(Action, &'static str)]);
+
+/// The keys shown in the help line of each mode, after its introduction.
+const HELP_LINE: [HelpRow; 9] = [
+    ("normal", "Press ", &[
+        (Action::Quit, "to exit"),
+        (Action::EnterInsert, "to enter insert mode"),
+        (Action::ToggleStderr, "to show/hide stderr"),
+        (Action::NextOutputJob, "to switch the output to a job"),
+        (Action::ShowDirs, "to show the directory stack"),
+    ]),
+    ("vi", "vi command mode: ", &[
+        (Action::Run, "to launch"),
+        (Action::Quit, "to exit"),
+        (Action::ShowDirs, "for the directory stack"),
+    ]),
+    ("editing", "Press ", &[
+        (Action::ExitInsert, "to exit insert mode"),
+        (Action::Complete, "to enter completion mode"),
+        (Action::BrowseHistory, "to enter history mode"),
+        (Action::SearchHistory, "to search it"),
+        (Action::Run, "to launch command"),
+    ]),
+    ("completion", "Type to narrow, press ", &[
+        (Action::CancelCompletion, "to exit completion"),
+        (Action::NextCompletion, "to select"),
+        (Action::AcceptCompletion, "to accept"),
+    ]),
+    ("history", "Press ", &[
+        (Action::CancelHistory, "to exit history mode"),
+        (Action::HistoryNext, "to select history element"),
+        (Action::AcceptHistory, "to accept history"),
+    ]),
+    ("search", "Type to search, press ", &[
+        (Action::NextMatch, "for the next match"),
+        (Action::AcceptMatch, "to use it"),
+        (Action::CancelSearch, "to exit search mode"),
+    ]),
+    ("dirs", "Press ", &[
+        (Action::CloseDirs, "to close the directory stack"),
+        (Action::NextDir, "to select a directory"),
+        (Action::JumpToDir, "to jump to it"),
+    ]),
+    ("helper", "Press ", &[(Action::CloseHelper, "to exit helper mode")]),
+    ("output", "Press ", &[
+        (Action::Detach, "to detach the command"),
+        (Action::Interrupt, "to interrupt it"),
+        (Action::StopJob, "to stop it"),
+    ]),
+];
+
+/// The help line of the current mode, with the keys it is bound to.
+fn help_line(app: &App) -> Vec<Span<'_>> {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let mode = app.binding_mode();
+    let (_, intro, actions) = HELP_LINE.iter().find(|(name, _, _)| *name == mode).unwrap();
+    let mut msg = vec![Span::raw(*intro)];
+    if mode == "vi" {
+        msg.push(Span::styled("i", bold));
+        msg.push(Span::raw(" to insert, "));
+        msg.push(Span::styled("v", bold));
+        msg.push(Span::raw(" to open $EDITOR, "));
+    }
+    let mut first = true;
+    for (action, text) in actions.iter() {
+        let keys = app.bindings.keys(mode, *action);
+        if keys.is_empty() {
+            continue;
+        }
+        if !first {
+            msg.push(Span::raw(", "));
+        }
+        first = false;
+        msg.push(Span::styled(keys.join("/"), bold));
+        msg.push(Span::raw(format!(" {}", text)));
+    }
+    msg.push(Span::raw("."));
+    msg
+}
+
+/// The value of a placeholder of the prompt format, such as `cwd` for
+/// `{cwd}`, with its color. `None` for unknown placeholders.
+fn prompt_value(app: &App, name: &str) -> Option<(String, Option<Color>)> {
+    let (error, success) = (color(app.config.theme.error), color(app.config.theme.success));
+    let value = match name {
+        "cwd" => (app.path.clone(), None),
+        "dir" => {
+            let dir = app.path.rsplit('/').find(|part| !part.is_empty());
+            (dir.unwrap_or("/").to_string(), None)
+        }
+        "user" => (app.var("USER").unwrap_or_default().to_string(), None),
+        "host" => (hostname(), None),
+        "status" => match app.status {
+            None => (String::new(), None),
+            Some(status) => match (status.code, status.signal) {
+                (_, Some(signal)) => (format!("✗ {}", signal_name(signal)), Some(error)),
+                (0, None) => (format!("✓ {}", format_duration(status.duration)), Some(success)),
+                (code, None) => (format!("✗ {}", code), Some(error)),
+            },
+        },
+        "jobs" => {
+            let running = app.jobs.iter().filter(|job| job.state == JobState::Running).count();
+            let stopped = app.jobs.iter().filter(|job| job.state == JobState::Stopped).count();
+            let mut text = String::new();
+            if running + stopped > 0 {
+                text.push_str("jobs:");
+                if running > 0 {
+                    text.push_str(&format!(" {} running", running));
+                }
+                if stopped > 0 {
+                    text.push_str(&format!(" {} stopped", stopped));
+                }
+            }
+            (text, Some(color(app.config.theme.accent)))
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// The title of the input box, from the prompt format of the config. An
+/// empty placeholder takes the space before it away.
+fn input_title(app: &App) -> Spans<'_> {
+    let mut title: Vec<Span> = Vec::new();
+    let mut literal = String::new();
+    let mut rest = app.config.prompt.as_str();
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        literal.push_str(&rest[..start]);
+        match prompt_value(app, &rest[start + 1..end]) {
+            Some((value, _)) if value.is_empty() => {
+                if literal.ends_with(' ') {
+                    literal.pop();
+                }
+            }
+            Some((value, color)) => {
+                title.push(Span::raw(std::mem::take(&mut literal)));
+                match color {
+                    Some(color) => title.push(Span::styled(value, Style::default().fg(color))),
+                    None => title.push(Span::raw(value)),
+                }
+            }
+            None => literal.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    literal.push_str(rest);
+    title.push(Span::raw(literal));
+
+    Spans::from(title)
+}
+
+/// The color of the terminal for a color of the theme.
+fn color(color: ThemeColor) -> Color {
+    match color {
+        ThemeColor::Reset => Color::Reset,
+        ThemeColor::Black => Color::Black,
+        ThemeColor::Red => Color::Red,
+        ThemeColor::Green => Color::Green,
+        ThemeColor::Yellow => Color::Yellow,
+        ThemeColor::Blue => Color::Blue,
+        ThemeColor::Magenta => Color::Magenta,
+        ThemeColor::Cyan => Color::Cyan,
+        ThemeColor::Gray => Color::Gray,
+        ThemeColor::DarkGray => Color::DarkGray,
+        ThemeColor::LightRed => Color::LightRed,
+        ThemeColor::LightGreen => Color::LightGreen,
+        ThemeColor::LightYellow => Color::LightYellow,
+        ThemeColor::LightBlue => Color::LightBlue,
+        ThemeColor::LightMagenta => Color::LightMagenta,
+        ThemeColor::LightCyan => Color::LightCyan,
+        ThemeColor::White => Color::White,
+        ThemeColor::Rgb(r, g, b) => Color::Rgb(r, g, b),
+        ThemeColor::Indexed(index) => Color::Indexed(index),
+    }
+}
+
 pub fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -216,107 +581,77 @@ pub fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
         )
         .split(f.size());
 
-    let (msg, style) = match app.input_mode {
-        InputMode::Normal => (
-            vec![
-                Span::raw("Press "),
-                Span::styled("q", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to exit, "),
-                Span::styled("i", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to enter insert mode."),
-            ],
-            Style::default().add_modifier(Modifier::RAPID_BLINK),
-        ),
-        InputMode::Editing => (
-            vec![
-                Span::raw("Press "),
-                Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to exit insert mode, "),
-                Span::styled("Tab", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to enter completion mode, "),
-                Span::styled("Down", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to enter history mode, "),
-                Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to launch command."),
-            ],
-            Style::default(),
-        ),
-        InputMode::Completion => (
-            vec![
-                Span::raw("Press "),
-                Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to exit completion mode, "),
-                Span::styled("Tab", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to select completion element, "),
-                Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to accept completion."),
-            ],
-            Style::default(),
-        ),
-        InputMode::History => (
-            vec![
-                Span::raw("Press "),
-                Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to exit history mode, "),
-                Span::styled("Tab", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to select history element, "),
-                Span::styled("Enter", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to accept history."),
-            ],
-            Style::default(),
-        ),
-        InputMode::Helper => (
-            vec![
-                Span::raw("Press "),
-                Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to exit helper mode."),
-            ],
-            Style::default(),
-        ),
-        InputMode::Output => (
-            vec![
-                Span::raw("Press "),
-                Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(" to exit output mode."),
-            ],
-            Style::default(),
-        ),
+    let style = match app.input_mode {
+        InputMode::Normal if app.keymap == Keymap::Vi => Style::default(),
+        InputMode::Normal => Style::default().add_modifier(Modifier::RAPID_BLINK),
+        _ => Style::default(),
     };
 
-    let mut text = Text::from(Spans::from(msg));
+    let mut text = Text::from(Spans::from(help_line(app)));
     text.patch_style(style);
     let help_message = Paragraph::new(text);
     f.render_widget(help_message, chunks[0]);
 
+    // Scrolled sideways so that the cursor stays in the box.
+    let cursor = app.input.get(..app.editor.cursor).unwrap_or(&app.input).width() as u16;
+    let scroll = (cursor + 1).saturating_sub(chunks[1].width.saturating_sub(2));
     let input = Paragraph::new(app.input.as_ref())
+        .scroll((0, scroll))
         .style(match app.input_mode {
             InputMode::Normal => Style::default(),
-            InputMode::Editing => Style::default().fg(Color::Yellow),
+            InputMode::Editing => Style::default().fg(color(app.config.theme.accent)),
             InputMode::Completion => Style::default(),
             InputMode::History => Style::default(),
             InputMode::Helper => Style::default(),
             InputMode::Output => Style::default(),
+            InputMode::Dirs => Style::default(),
+            InputMode::Search => Style::default(),
         })
-        .block(Block::default().borders(Borders::ALL).title(&*app.path));
+        .block(Block::default().borders(Borders::ALL).title(input_title(app)));
     f.render_widget(input, chunks[1]);
 
     match app.input_mode {
+        InputMode::Normal if app.keymap == Keymap::Vi => {
+            f.set_cursor(chunks[1].x + cursor - scroll + 1, chunks[1].y + 1)
+        }
         InputMode::Normal => {}
         InputMode::Editing => {
-            f.set_cursor(chunks[1].x + app.input.width() as u16 + 1, chunks[1].y + 1)
+            f.set_cursor(chunks[1].x + cursor - scroll + 1, chunks[1].y + 1)
         }
         InputMode::Completion => {
-            display_completion(f, app, chunks[2]);
+            f.set_cursor(chunks[1].x + cursor - scroll + 1, chunks[1].y + 1)
         }
         InputMode::History => {
             display_history(f, app, chunks[2]);
         }
         InputMode::Helper => {
-            let message = construct_message(&app.helper);
-            create_popup(f, app, message, 30, 40, "Helper");
+            let help = app.help();
+            let message = construct_message(&app.config.theme, &help);
+            create_popup(f, app, message, 60, 80, "Helper");
         }
         InputMode::Output => {}
+        InputMode::Dirs => {}
+        InputMode::Search => {
+            display_search(f, app, chunks[2], chunks[3]);
+        }
+    }
+
+    if !matches!(app.input_mode, InputMode::Search) {
+        display_output(f, app, chunks[3]);
     }
 
-    display_output(f, app, chunks[3]);
+    // Drawn last so that they cover the output.
+    match app.input_mode {
+        InputMode::Completion => {
+            let area = Rect::new(
+                chunks[2].x,
+                chunks[2].y,
+                chunks[2].width,
+                chunks[2].height + chunks[3].height,
+            );
+            display_completion(f, app, area);
+        }
+        InputMode::Dirs => display_dirs(f, app),
+        _ => {}
+    }
 }
@@ -4,7 +4,7 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     Result,
 };
-use std::{error::Error, io};
+use std::io;
 use tui::backend::{Backend, CrosstermBackend};
 use tui::Terminal;
 
@@ -20,7 +20,29 @@ async fn main() -> Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let app = App::default();
+    let mut app = App::default();
+    if let Err(err) = app.load_config() {
+        app.output
+            .push_str(&format!("<c>{}</c>\n", escape_markup(&err)));
+    }
+    if let Err(errors) = app.load_keys() {
+        for err in errors {
+            app.output
+                .push_str(&format!("<c>{}</c>\n", escape_markup(&err)));
+        }
+    }
+    if let Err(err) = completion::load_completions(&app) {
+        app.output
+            .push_str(&format!("<c>{}</c>\n", escape_markup(&err)));
+    }
+    // The rc file may set HISTFILE, HISTSIZE or HISTCONTROL.
+    run_rc(&mut app).await;
+    if let Err(err) = app.load_history() {
+        app.output.push_str(&format!(
+            "<c>history: {}</c>\n",
+            escape_markup(&err.to_string())
+        ));
+    }
     let res = run_app(&mut terminal, app).await;
 
     disable_raw_mode()?;
@@ -31,18 +53,20 @@ async fn main() -> Result<()> {
     )?;
     terminal.show_cursor()?;
 
-    if let Err(err) = res {
-        println!("{:?}", err)
+    match res {
+        // The status of the last command, as given to `exit`.
+        Ok(code) => std::process::exit(code),
+        Err(err) => println!("{:?}", err),
     }
 
     Ok(())
 }
 
-async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
+async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<i32> {
     terminal.draw(|f| ui(f, &app))?;
     loop {
-        if events(&mut app, terminal).await.unwrap() == false {
-            return Ok(());
+        if !events(&mut app, terminal).await.unwrap() {
+            return Ok(app.last_status());
         }
     }
 }